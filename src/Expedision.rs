@@ -19,20 +19,23 @@ use sf_api::gamestate::items::PlayerItemPlace;
 use sf_api::gamestate::rewards::Event::{EpicQuestExtravaganza, ExceptionalXPEvent, OneBeerTwoBeerFreeBeer};
 use sf_api::gamestate::rewards::{Reward, RewardType};
 use sf_api::gamestate::tavern::CurrentAction::Expedition;
-use sf_api::gamestate::tavern::{ExpeditionEncounter, ExpeditionStage, ExpeditionThing};
+use sf_api::gamestate::tavern::{AvailableExpedition, ExpeditionEncounter, ExpeditionStage, ExpeditionThing};
+use crate::config::{BotSettings, ExpeditionTarget};
+use crate::error::{BotError, BotResult};
 use crate::functions::{log_to_file, sell_the_worst_item, time_remaining};
 
 pub struct Exping<'a> {
     session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
 }
 
 impl<'a> Exping<'a> {
     // Accepts a mutable reference to SimpleSession
-    pub fn new(session: &'a mut SimpleSession) -> Self {
-        Exping { session }
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Exping { session, settings }
     }
 
-    pub async fn Exping(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn Exping(&mut self) -> BotResult<()> {
         loop {
             let gs = self.session.send_command(Command::Update).await?;
             let exp = &gs.tavern.expeditions;
@@ -55,20 +58,10 @@ impl<'a> Exping<'a> {
                         }
                         if gs.tavern.questing_preference == ExpeditionSetting::PreferQuests {
                             // This means we could do expeditions, but they are
-                            // disabled in the settings
-                            if !gs.tavern.can_change_questing_preference() {
-                                log_to_file("Expeditions are disabled in the settings and that setting can not be changed today").await?;
-                                break;
-                            }
-                            log_to_file("Changing expedition setting").await?;
-                            self.session
-                                .send_command(
-                                    Command::SetQuestsInsteadOfExpeditions {
-                                        value: ExpeditionSetting::PreferExpeditions,
-                                    },
-                                )
-                                .await?;
-                            continue;
+                            // disabled in the settings. Changing that is up to
+                            // the tavern strategy
+                            log_to_file("Expeditions are disabled in the settings").await?;
+                            break;
                         }
                         log_to_file("There seem to be no expeditions").await?;
                         break;
@@ -76,9 +69,13 @@ impl<'a> Exping<'a> {
                     AvailableTasks::Expeditions(expeditions) => expeditions,
                 };
 
-                // We would normally have to choose which expedition is the best.
-                // For now we just choose the first one though
-                let target = expeditions.first().unwrap();
+                let Some((target_pos, target)) = choose_expedition(expeditions, gs.tavern.thirst_for_adventure_sec, self.settings) else {
+                    return Err(BotError::unexpected("Expeditions are available, but the list is empty"));
+                };
+                log_to_file(&format!(
+                    "Chose expedition #{} for {:?} ({}s of thirst)",
+                    target_pos, target.target, target.thirst_for_adventure_sec
+                )).await?;
 
                 // Make sure we have enough thirst for adventure to do the
                 // expeditions
@@ -90,27 +87,27 @@ impl<'a> Exping<'a> {
                     for event in events {
                         if event == ExceptionalXPEvent || event == EpicQuestExtravaganza || event == OneBeerTwoBeerFreeBeer {
                             let gs = self.session.send_command(Command::Update).await?;
-                            if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (10 + has_extra_beer as u8) {
+                            if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (self.settings.beer.max_beer_event + has_extra_beer as u8) {
                                 log_to_file("Buying beer").await?;
                                 self.session.send_command(Command::BuyBeer).await?;
                                 a = true;
                                 continue;
                             } else {
                                 log_to_file("Starting city guard").await?;
-                                self.session.send_command(Command::StartWork { hours: 10 }).await?;
+                                self.session.send_command(Command::StartWork { hours: self.settings.beer.city_guard_hours }).await?;
                                 break;
                             }
                         }
                     }
                     if !a {
                         let gs = self.session.send_command(Command::Update).await?;
-                        if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (0 + has_extra_beer as u8) {
+                        if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (self.settings.beer.max_beer + has_extra_beer as u8) {
                             log_to_file("Buying beer").await?;
                             self.session.send_command(Command::BuyBeer).await?;
                             continue;
                         } else {
                             log_to_file("Starting city guard").await?;
-                            self.session.send_command(Command::StartWork { hours: 10 }).await?;
+                            self.session.send_command(Command::StartWork { hours: self.settings.beer.city_guard_hours }).await?;
                             break;
                         }
                     } else {
@@ -121,7 +118,7 @@ impl<'a> Exping<'a> {
                 // We should be all good to start the expedition
                 log_to_file("Starting expedition").await?;
                 self.session
-                    .send_command(Command::ExpeditionStart { pos: 0 })
+                    .send_command(Command::ExpeditionStart { pos: target_pos })
                     .await?;
                 continue;
             };
@@ -138,14 +135,10 @@ impl<'a> Exping<'a> {
                         continue; // Changed from panic to continue with logging
                     }
                     log_to_file("Picking reward").await?;
-                    let priority_order = [
-                        RewardType::LuckyCoins,
-                        RewardType::Mushrooms,
-                        RewardType::Stone,
-                        RewardType::Wood,
-                        RewardType::QuicksandGlass,
-                        RewardType::Silver,
-                    ];
+                    let priority_order: Vec<RewardType> = self.settings.expeditions.reward_priority
+                        .iter()
+                        .map(|&r| r.into())
+                        .collect();
 
                     let selected_reward = rewards.iter().enumerate()
                         .max_by(|(_, a), (_, b)| {
@@ -170,8 +163,7 @@ impl<'a> Exping<'a> {
                         log_to_file("No crossroads to choose from").await?;
                         continue; // Handle this scenario without panicking
                     }
-                    let active_expedition = gs.tavern.expeditions.active().expect("No active expedition found");
-                    let target_thing = &active_expedition.target_thing;
+                    let target_thing = &active.target_thing;
                     const MAX_PRIORITY_LENGTH: usize = 6; // Adjust based on the longest priority list
 
                     // Define a priority or logic for which encounter to choose
@@ -302,10 +294,12 @@ impl<'a> Exping<'a> {
                         Command::ExpeditionSkipWait {
                             typ: TimeSkip::Glass,
                         }
-                    } else {
-                        log_to_file(&format!("Waiting {}s until next expedition step", remaining.as_secs())).await?;
-                        sleep(remaining).await;
+                    } else if remaining == Duration::ZERO {
                         Command::Update
+                    } else {
+                        // The scheduler wakes us up again once the wait is over
+                        log_to_file(&format!("{}s until next expedition step", remaining.as_secs())).await?;
+                        break;
                     }
                 }
                 ExpeditionStage::Unknown => {
@@ -314,7 +308,7 @@ impl<'a> Exping<'a> {
                 },
             };
             sleep(Duration::from_secs(1)).await;
-            self.session.send_command(cmd).await.unwrap();
+            self.session.send_command(cmd).await?;
         }
 
         Ok(())
@@ -323,3 +317,55 @@ impl<'a> Exping<'a> {
 
 }
 
+/// Picks the expedition to start. Targets listed in the config win in the
+/// order given there. Between equally preferred ones we take the one we
+/// can afford without beer, then the one costing the least thirst, since
+/// every expedition ends with the same amount of reward picks. The rewards
+/// themselves are not scored here: they are only offered at the end of an
+/// expedition, where `reward_priority` picks among them
+fn choose_expedition<'e>(expeditions: &'e [AvailableExpedition], thirst_left: u32, settings: &BotSettings) -> Option<(usize, &'e AvailableExpedition)> {
+    let preferred = &settings.expeditions.preferred_targets;
+    expeditions
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, e)| expedition_rank(&e.target, e.thirst_for_adventure_sec, thirst_left, preferred))
+}
+
+/// Sort key of an expedition, lower is better
+fn expedition_rank(target: &ExpeditionThing, thirst: u32, thirst_left: u32, preferred: &[ExpeditionTarget]) -> (usize, bool, u32) {
+    let rank = preferred
+        .iter()
+        .position(|&t| ExpeditionThing::from(t) == *target)
+        .unwrap_or(preferred.len());
+    let needs_beer = thirst > thirst_left;
+    (rank, needs_beer, thirst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_targets_win_in_config_order() {
+        let preferred = [ExpeditionTarget::Unicorn, ExpeditionTarget::Dragon];
+        let unicorn = expedition_rank(&ExpeditionThing::Unicorn, 900, 600, &preferred);
+        let dragon = expedition_rank(&ExpeditionThing::Dragon, 300, 600, &preferred);
+        let cake = expedition_rank(&ExpeditionThing::Cake, 300, 600, &preferred);
+        assert!(unicorn < dragon);
+        assert!(dragon < cake);
+    }
+
+    #[test]
+    fn affordable_expeditions_beat_ones_needing_beer() {
+        let cheap = expedition_rank(&ExpeditionThing::Cake, 600, 600, &[]);
+        let beer = expedition_rank(&ExpeditionThing::Dragon, 601, 600, &[]);
+        assert!(cheap < beer);
+    }
+
+    #[test]
+    fn less_thirst_wins_between_equals() {
+        let short = expedition_rank(&ExpeditionThing::Cake, 300, 600, &[]);
+        let long = expedition_rank(&ExpeditionThing::Dragon, 450, 600, &[]);
+        assert!(short < long);
+    }
+}
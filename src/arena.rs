@@ -1,68 +1,145 @@
 use std::time::Duration;
+use chrono::Local;
 use sf_api::command::Command;
 use sf_api::command::Command::Update;
 use sf_api::SimpleSession;
+use crate::config::BotSettings;
+use crate::error::BotResult;
 use crate::functions::{log_to_file, time_remaining};
+use crate::simulation::win_rate_vs_player;
+use crate::arena_history::{ArenaHistory, ArenaRecord};
+use crate::sessions::CharacterId;
 
 pub struct Arena<'a> {
     session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
 }
 
 impl<'a> Arena<'a> {
     // Accepts a mutable reference to SimpleSession
-    pub fn new(session: &'a mut SimpleSession) -> Self {
-        Arena { session }
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Arena { session, settings }
     }
 
-    async fn find_weakest_player(&mut self) -> Option<String> {
-        let gs = match self.session.send_command(Command::Update).await {
-            Ok(gs) => gs,
-            Err(_) => return None,
-        };
-        let a = gs.arena.enemy_ids.clone();
-        let mut lowest_enemy_attributes = u32::MAX;
-        let mut lowest_enemy_name = None;
-        let mut is_first_enemy = true;
+    /// Simulates a fight against each of the three players the arena offers,
+    /// mixes that with how we did against them before and returns the best
+    /// one, skipping players we recently lost to if there is any other choice
+    async fn find_best_opponent(&mut self, history: &ArenaHistory) -> BotResult<Option<Opponent>> {
+        let gs = self.session.send_command(Command::Update).await?;
+        let enemies = gs.arena.enemy_ids.clone();
+        let iterations = self.settings.simulation.iterations;
+        let mut candidates: Vec<(Opponent, f64, bool)> = Vec::new();
 
-        for enemy in a {
-            if let Ok(_) = self.session.send_command(Command::ViewPlayer { ident: enemy.to_string() }).await {
-                if let Some(player) = self.session.game_state().unwrap().lookup.lookup_pid(enemy) {
-                    let enemy_attributes = player.base_attributes.values().sum::<u32>() + player.bonus_attributes.values().sum::<u32>()
-                        + ((player.min_damage_base + player.max_damage_base)/2);
-                    if is_first_enemy || enemy_attributes < lowest_enemy_attributes {
-                        lowest_enemy_attributes = enemy_attributes;
-                        lowest_enemy_name = Some(player.name.clone());
-                        is_first_enemy = false;
-                    }
-                }
+        for enemy in enemies {
+            if let Err(e) = self.session.send_command(Command::ViewPlayer { ident: enemy.to_string() }).await {
+                log_to_file(&format!("Could not look at arena enemy {}: {:?}", enemy, e)).await?;
+                continue;
             }
+            let Some(gs) = self.session.game_state() else {
+                continue;
+            };
+            let Some(player) = gs.lookup.lookup_pid(enemy) else {
+                continue;
+            };
+            let win_rate = win_rate_vs_player(gs, player, iterations);
+            let adjusted = history.adjusted_win_rate(&player.name, win_rate);
+            let lost_recently = history.lost_recently(&player.name, self.settings.arena.avoid_loss_days);
+            log_to_file(&format!(
+                "{} (level {}): {:.1}% estimated win chance, {:.1}% with our history{}",
+                player.name, player.level, win_rate * 100.0, adjusted * 100.0,
+                if lost_recently { ", lost recently" } else { "" }
+            )).await?;
+            let opponent = Opponent { name: player.name.clone(), level: player.level, win_rate };
+            candidates.push((opponent, adjusted, lost_recently));
         }
 
-        lowest_enemy_name
+        let best = |avoid_losses: bool| {
+            candidates
+                .iter()
+                .filter(|(_, _, lost)| !(avoid_losses && *lost))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(o, _, _)| o.clone())
+        };
+        Ok(best(true).or_else(|| best(false)))
     }
 
-    pub async fn fight_arena(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn fight_arena(&mut self) -> BotResult<()> {
         let gs = self.session.send_command(Update).await?;
-        let rem = time_remaining(gs.arena.next_free_fight.unwrap());
+        let character = gs.character.name.clone();
+        let server = CharacterId::of(self.session).map(|id| id.server).unwrap_or_default();
+        let mut history = ArenaHistory::load(&server, &character)?;
+        let settings = &self.settings.arena;
+
+        loop {
+            let gs = self.session.send_command(Update).await?;
+            // No timer means the next fight is free right away
+            let rem = gs.arena.next_free_fight.map(time_remaining).unwrap_or_default();
+            let honor_before = gs.character.honor;
 
-        if rem <= Duration::from_secs(0) {
-            if let Some(weakest_player) = self.find_weakest_player().await {
-                self.session.send_command(Command::Fight {
-                    name: weakest_player,
-                    use_mushroom: false,
-                }).await?;
-                if let Some(game_state) = self.session.game_state() {
-                    log_to_file(&format!("Result of a fight {:?}", game_state.last_fight.clone().unwrap().has_player_won)).await?;
-                } else {
-                    eprintln!("Failed to get game state.");
-                }
+            let use_mushroom = if rem <= Duration::from_secs(0) {
+                false
+            } else if settings.mushroom_fights
+                && history.mushrooms_spent_today() < settings.mushroom_daily_budget
+                && gs.character.mushrooms > 0
+            {
+                true
             } else {
-                eprintln!("No weakest player found.");
+                log_to_file(&format!("{:?} minutes until the next arena fight is available", rem/60)).await?;
+                break;
+            };
+
+            let Some(opponent) = self.find_best_opponent(&history).await? else {
+                log_to_file("No arena opponent found").await?;
+                break;
+            };
+            if use_mushroom && opponent.win_rate < settings.mushroom_min_win_rate {
+                log_to_file(&format!(
+                    "Not spending a mushroom on {} with only {:.1}% estimated win chance",
+                    opponent.name, opponent.win_rate * 100.0
+                )).await?;
+                break;
+            }
+
+            let gs = self.session.send_command(Command::Fight {
+                name: opponent.name.clone(),
+                use_mushroom,
+            }).await?;
+            let honor_change = gs.character.honor as i64 - honor_before as i64;
+            let Some(fight) = gs.last_fight.as_ref() else {
+                log_to_file("The fight did not return a result").await?;
+                break;
+            };
+            log_to_file(&format!(
+                "Result of a {}fight against {}: won {:?} (estimated {:.1}%), honor {:+}",
+                if use_mushroom { "mushroom " } else { "" },
+                opponent.name, fight.has_player_won, opponent.win_rate * 100.0, honor_change
+            )).await?;
+            history.add(ArenaRecord {
+                server: server.clone(),
+                character: character.clone(),
+                opponent: opponent.name,
+                opponent_level: opponent.level,
+                won: fight.has_player_won,
+                honor_change,
+                estimated_win_rate: opponent.win_rate,
+                used_mushroom: use_mushroom,
+                timestamp: Local::now(),
+            })?;
+            history.report_after_fight().await?;
+
+            if !settings.mushroom_fights {
+                break;
             }
-        }else{
-            log_to_file(&format!("{:?} minutes until the next arena fight is available", rem/60)).await?;
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+struct Opponent {
+    name: String,
+    level: u16,
+    /// Simulated chance to win against them
+    win_rate: f64,
+}
@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use crate::error::BotResult;
+use crate::functions::log_to_file;
+use crate::history;
+
+const ARENA_HISTORY_FILE: &str = "arena_history.jsonl";
+/// Days the report covers on the first fight of a day
+const REPORT_DAYS: usize = 7;
+
+/// One arena fight of one of our characters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArenaRecord {
+    /// Characters of different servers can share a name
+    #[serde(default)]
+    pub server: String,
+    pub character: String,
+    pub opponent: String,
+    pub opponent_level: u16,
+    pub won: bool,
+    pub honor_change: i64,
+    /// The simulated win chance we picked the opponent with
+    pub estimated_win_rate: f64,
+    /// Whether we paid a mushroom to skip the arena timer
+    #[serde(default)]
+    pub used_mushroom: bool,
+    pub timestamp: DateTime<Local>,
+}
+
+impl ArenaRecord {
+    /// Records written before the server was stored count for every server
+    fn belongs_to(&self, server: &str, character: &str) -> bool {
+        self.character == character && (self.server.is_empty() || self.server == server)
+    }
+}
+
+/// All past arena fights of one character
+pub struct ArenaHistory {
+    character: String,
+    records: Vec<ArenaRecord>,
+}
+
+impl ArenaHistory {
+    pub fn load(server: &str, character: &str) -> BotResult<Self> {
+        let records = history::read_all::<ArenaRecord>(ARENA_HISTORY_FILE)?
+            .into_iter()
+            .filter(|r| r.belongs_to(server, character))
+            .collect();
+        Ok(ArenaHistory { character: character.to_string(), records })
+    }
+
+    pub fn add(&mut self, record: ArenaRecord) -> BotResult<()> {
+        history::append(ARENA_HISTORY_FILE, &record)?;
+        self.records.push(record);
+        Ok(())
+    }
+
+    /// Whether we lost against this opponent within the last `days` days
+    pub fn lost_recently(&self, opponent: &str, days: i64) -> bool {
+        let since = Local::now() - chrono::Duration::days(days);
+        self.records
+            .iter()
+            .any(|r| r.opponent == opponent && !r.won && r.timestamp >= since)
+    }
+
+    /// Mixes the simulated win chance with our actual results against this
+    /// opponent. Every past fight counts as much as the simulation does
+    pub fn adjusted_win_rate(&self, opponent: &str, simulated: f64) -> f64 {
+        let (fights, wins) = self
+            .records
+            .iter()
+            .filter(|r| r.opponent == opponent)
+            .fold((0u32, 0u32), |(f, w), r| (f + 1, w + r.won as u32));
+        (wins as f64 + simulated) / (fights as f64 + 1.0)
+    }
+
+    /// Mushrooms spent on arena fights since midnight
+    pub fn mushrooms_spent_today(&self) -> u32 {
+        let today = Local::now().date_naive();
+        self.records
+            .iter()
+            .filter(|r| r.used_mushroom && r.timestamp.date_naive() == today)
+            .count() as u32
+    }
+
+    /// Wins and fights per day
+    pub fn daily_results(&self) -> BTreeMap<NaiveDate, (u32, u32)> {
+        let mut days: BTreeMap<NaiveDate, (u32, u32)> = BTreeMap::new();
+        for r in &self.records {
+            let day = days.entry(r.timestamp.date_naive()).or_default();
+            day.0 += r.won as u32;
+            day.1 += 1;
+        }
+        days
+    }
+
+    /// Days to report after a fight: the whole last week on the first fight of
+    /// a day, otherwise only today
+    fn days_to_report(&self) -> usize {
+        let today = Local::now().date_naive();
+        let fights_today = self.records.iter().filter(|r| r.timestamp.date_naive() == today).count();
+        if fights_today <= 1 { REPORT_DAYS } else { 1 }
+    }
+
+    /// Logs the win rates of the days `days_to_report` picks
+    pub async fn report_after_fight(&self) -> BotResult<()> {
+        self.report(self.days_to_report()).await
+    }
+
+    /// Logs the arena win rate of the last `days` days
+    pub async fn report(&self, days: usize) -> BotResult<()> {
+        for (day, (wins, fights)) in self.daily_results().iter().rev().take(days) {
+            log_to_file(&format!(
+                "Arena {} on {}: won {}/{} ({:.0}%)",
+                self.character, day, wins, fights,
+                *wins as f64 / *fights as f64 * 100.0
+            )).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fight(opponent: &str, won: bool, days_ago: i64, used_mushroom: bool) -> ArenaRecord {
+        ArenaRecord {
+            server: "s1.sfgame.net".to_string(),
+            character: "Me".to_string(),
+            opponent: opponent.to_string(),
+            opponent_level: 100,
+            won,
+            honor_change: if won { 100 } else { -100 },
+            estimated_win_rate: 0.5,
+            used_mushroom,
+            timestamp: Local::now() - chrono::Duration::days(days_ago),
+        }
+    }
+
+    fn history(records: Vec<ArenaRecord>) -> ArenaHistory {
+        ArenaHistory { character: "Me".to_string(), records }
+    }
+
+    #[test]
+    fn adjusted_win_rate_mixes_results_with_the_simulation() {
+        let history = history(vec![
+            fight("Foe", true, 1, false),
+            fight("Foe", false, 2, false),
+            fight("Foe", false, 3, false),
+        ]);
+        assert_eq!(history.adjusted_win_rate("Foe", 0.8), (1.0 + 0.8) / 4.0);
+        assert_eq!(history.adjusted_win_rate("Stranger", 0.8), 0.8);
+    }
+
+    #[test]
+    fn only_recent_losses_count() {
+        let history = history(vec![
+            fight("Old", false, 5, false),
+            fight("Recent", false, 1, false),
+            fight("Beaten", true, 1, false),
+        ]);
+        assert!(!history.lost_recently("Old", 3));
+        assert!(history.lost_recently("Recent", 3));
+        assert!(!history.lost_recently("Beaten", 3));
+    }
+
+    #[test]
+    fn counts_todays_mushroom_fights() {
+        let history = history(vec![
+            fight("A", true, 0, true),
+            fight("B", false, 0, true),
+            fight("C", true, 0, false),
+            fight("D", true, 2, true),
+        ]);
+        assert_eq!(history.mushrooms_spent_today(), 2);
+    }
+
+    #[test]
+    fn records_are_kept_apart_by_server() {
+        let record = fight("Foe", true, 0, false);
+        assert!(record.belongs_to("s1.sfgame.net", "Me"));
+        assert!(!record.belongs_to("s2.sfgame.net", "Me"));
+        assert!(!record.belongs_to("s1.sfgame.net", "Other"));
+        let legacy = ArenaRecord { server: String::new(), ..record };
+        assert!(legacy.belongs_to("s2.sfgame.net", "Me"));
+    }
+
+    #[test]
+    fn first_fight_of_the_day_reports_the_week() {
+        let first = history(vec![fight("A", true, 2, false), fight("B", true, 0, false)]);
+        assert_eq!(first.days_to_report(), REPORT_DAYS);
+        let second = history(vec![fight("A", true, 0, false), fight("B", true, 0, false)]);
+        assert_eq!(second.days_to_report(), 1);
+    }
+}
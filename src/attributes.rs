@@ -0,0 +1,75 @@
+use std::time::Duration;
+use sf_api::command::{AttributeType, Command};
+use sf_api::SimpleSession;
+use crate::config::BotSettings;
+use crate::error::BotResult;
+use crate::functions::{log_to_file, main_attribute};
+use tokio::time::sleep;
+
+/// Upper bound of points bought in one cycle. Each point is one request, so
+/// this keeps a cycle short and the server load low
+const MAX_POINTS_PER_CYCLE: u32 = 20;
+/// Pause between two purchases
+const BUY_DELAY: Duration = Duration::from_millis(500);
+
+pub struct Attributes<'a> {
+    session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
+}
+
+impl<'a> Attributes<'a> {
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Attributes { session, settings }
+    }
+
+    /// Buys base attributes one point at a time, always the one that is
+    /// furthest below its target ratio, until the next point would eat into
+    /// the silver reserve
+    pub async fn level_attributes(&mut self) -> BotResult<()> {
+        let targets = &self.settings.attributes;
+        let mut gs = self.session.send_command(Command::Update).await?;
+        let mut bought = 0;
+        let mut spent = 0;
+
+        while bought < MAX_POINTS_PER_CYCLE {
+            let main = main_attribute(gs.character.class);
+            let weights = [
+                (main, targets.main),
+                (AttributeType::Constitution, targets.constitution),
+                (AttributeType::Luck, targets.luck),
+            ];
+            let Some((attribute, current)) = weights
+                .iter()
+                .filter(|(_, weight)| *weight > 0.0)
+                .map(|(attr, weight)| (*attr, gs.character.attribute_basis[*attr], *weight))
+                .min_by(|a, b| (a.1 as f64 / a.2).total_cmp(&(b.1 as f64 / b.2)))
+                .map(|(attr, current, _)| (attr, current))
+            else {
+                break;
+            };
+
+            let silver = gs.character.silver;
+            let cost = gs.character.attribute_costs[attribute];
+            if silver < targets.silver_reserve + cost {
+                break;
+            }
+            if bought > 0 {
+                sleep(BUY_DELAY).await;
+            }
+            gs = self.session
+                .send_command(Command::IncreaseAttribute { attribute, increase_to: current + 1 })
+                .await?;
+            if gs.character.silver >= silver {
+                // Nothing was bought, the server did not accept the price
+                break;
+            }
+            spent += silver - gs.character.silver;
+            bought += 1;
+        }
+
+        if bought > 0 {
+            log_to_file(&format!("Bought {} attribute points for {} silver", bought, spent)).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde::Deserialize;
+use sf_api::gamestate::fortress::FortressBuildingType;
+use sf_api::gamestate::rewards::RewardType;
+use sf_api::gamestate::tavern::ExpeditionThing;
+use crate::error::BotResult;
+
+/// Everything that can be tuned without recompiling. Loaded once at startup
+/// from the file given with `--config` (defaults to `config.toml`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Longest time in seconds the main loop sleeps. Activities that have
+    /// nothing to do and nothing to wait for are retried after this long
+    pub sleep_secs: u64,
+    /// Login data. Environment variables take precedence over these
+    pub account: AccountConfig,
+    /// Classic characters on single servers, played next to the account above
+    pub servers: Vec<ServerAccount>,
+    /// Settings used by every character that has no own entry in `characters`
+    pub defaults: BotSettings,
+    /// Per character overrides, keyed by the character name. Every entry is
+    /// merged over `defaults` when the file is loaded
+    pub characters: HashMap<String, BotSettings>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sleep_secs: 60,
+            account: AccountConfig::default(),
+            servers: Vec::new(),
+            defaults: BotSettings::default(),
+            characters: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> BotResult<Config> {
+        let content = std::fs::read_to_string(path)?;
+        Config::parse(&content)
+    }
+
+    /// Parses a config file. The `[characters.X]` tables only hold what
+    /// differs from `[defaults]`, so they are merged over it before the
+    /// settings are read
+    pub fn parse(content: &str) -> BotResult<Config> {
+        let mut value: toml::Value = toml::from_str(content)?;
+        if let Some(table) = value.as_table_mut() {
+            let defaults = table.get("defaults").cloned()
+                .unwrap_or_else(|| toml::Value::Table(toml::Table::new()));
+            if let Some(toml::Value::Table(characters)) = table.get_mut("characters") {
+                for overrides in characters.values_mut() {
+                    let mut merged = defaults.clone();
+                    merge(&mut merged, overrides.clone());
+                    *overrides = merged;
+                }
+            }
+        }
+        Ok(value.try_into()?)
+    }
+
+    /// The settings that apply to the character with the given name
+    pub fn for_character(&self, name: &str) -> &BotSettings {
+        self.characters.get(name).unwrap_or(&self.defaults)
+    }
+}
+
+/// Recursively puts the values of `over` into `base`. Tables are merged key by
+/// key, everything else (including arrays) is replaced
+fn merge(base: &mut toml::Value, over: toml::Value) {
+    match (base, over) {
+        (toml::Value::Table(base), toml::Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Server url for a classic (non SSO) login, e.g. `s1.sfgame.net`
+    pub server: Option<String>,
+    /// Character name for a classic login, if it differs from `username`
+    pub character: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerAccount {
+    /// Server url, e.g. `s1.sfgame.net`
+    pub server: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotSettings {
+    pub activities: Activities,
+    pub arena: ArenaSettings,
+    pub attributes: AttributeSettings,
+    pub beer: BeerSettings,
+    pub dungeons: DungeonSettings,
+    pub equip: EquipSettings,
+    pub expeditions: ExpeditionSettings,
+    pub fortress: FortressSettings,
+    pub potions: PotionSettings,
+    pub quests: QuestSettings,
+    pub retries: RetrySettings,
+    pub selling: SellSettings,
+    pub shopping: ShopSettings,
+    pub simulation: SimulationSettings,
+    pub tavern: TavernSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Activities {
+    /// Buy gear from the weapon and magic shops, see `[shopping]`
+    pub shopping: bool,
+    pub equip: bool,
+    /// Give items nobody else needs to the tower companions
+    pub companions: bool,
+    /// Spend silver on base attributes, see `[attributes]`
+    pub attributes: bool,
+    /// Keep the potions of `[potions]` active
+    pub potions: bool,
+    /// Quests or expeditions, depending on the `[tavern]` strategy
+    pub tavern: bool,
+    pub dungeons: bool,
+    pub arena: bool,
+    /// Collect resources and upgrade buildings, see `[fortress]`
+    pub fortress: bool,
+}
+
+impl Default for Activities {
+    fn default() -> Self {
+        Activities {
+            shopping: false,
+            equip: true,
+            companions: true,
+            attributes: false,
+            potions: false,
+            tavern: true,
+            dungeons: true,
+            arena: true,
+            fortress: false,
+        }
+    }
+}
+
+/// How often each activity is run again after a network error. Shopping,
+/// attributes and potions default to 0: buying is all they do, and we can not
+/// tell whether the failed purchase went through. The other activities read
+/// the game state again before they spend anything, so a retry does not pay
+/// for the same thing twice
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetrySettings {
+    pub selling: u32,
+    pub shopping: u32,
+    pub equip: u32,
+    pub companions: u32,
+    pub attributes: u32,
+    pub potions: u32,
+    pub tavern: u32,
+    pub dungeons: u32,
+    pub arena: u32,
+    pub fortress: u32,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            selling: 2,
+            shopping: 0,
+            equip: 2,
+            companions: 2,
+            attributes: 0,
+            potions: 0,
+            tavern: 2,
+            dungeons: 2,
+            arena: 2,
+            fortress: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TavernStrategy {
+    Quests,
+    Expeditions,
+    /// Decide every day based on events and the quests on offer
+    Auto,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TavernSettings {
+    pub strategy: TavernStrategy,
+    /// Rough xp and silver (in copper) per thirst second an expedition yields
+    /// for this character. The rewards of expeditions are unknown before
+    /// starting them, so `Auto` only picks quests over expeditions if a quest
+    /// beats this estimate, scored by the `[quests]` objective
+    pub expedition_xp_per_thirst_sec: Option<f64>,
+    pub expedition_silver_per_thirst_sec: Option<f64>,
+    /// Used for the estimates that are left out: an expedition is assumed to
+    /// yield this many times what the average quest on offer does
+    pub expedition_quest_ratio: f64,
+}
+
+impl Default for TavernSettings {
+    fn default() -> Self {
+        TavernSettings {
+            strategy: TavernStrategy::Expeditions,
+            expedition_xp_per_thirst_sec: None,
+            expedition_silver_per_thirst_sec: None,
+            expedition_quest_ratio: 1.25,
+        }
+    }
+}
+
+/// Target ratio of the base attributes we buy. The main attribute is the one of
+/// the character class, e.g. intelligence for mages
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributeSettings {
+    pub main: f64,
+    pub constitution: f64,
+    pub luck: f64,
+    /// Silver (in copper, 100 = 1 silver coin) we never spend on attributes
+    pub silver_reserve: u64,
+}
+
+impl Default for AttributeSettings {
+    fn default() -> Self {
+        AttributeSettings {
+            main: 1.0,
+            constitution: 0.5,
+            luck: 0.25,
+            silver_reserve: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArenaSettings {
+    /// Players we lost to within this many days are only fought if all three
+    /// offered opponents beat us recently
+    pub avoid_loss_days: i64,
+    /// Skip the arena timer with mushrooms, e.g. during honor events
+    pub mushroom_fights: bool,
+    /// Only pay a mushroom if the simulated win chance is at least this high
+    pub mushroom_min_win_rate: f64,
+    /// Most mushrooms spent on arena fights per day
+    pub mushroom_daily_budget: u32,
+}
+
+impl Default for ArenaSettings {
+    fn default() -> Self {
+        ArenaSettings {
+            avoid_loss_days: 3,
+            mushroom_fights: false,
+            mushroom_min_win_rate: 0.9,
+            mushroom_daily_budget: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SellSettings {
+    /// Items that sell for less than this are dismantled at the blacksmith
+    /// instead, since their metal and arcane splinters are worth more. This
+    /// is a plain price threshold: the resources an item would give are not
+    /// known before dismantling it, so they are not compared to its price
+    pub dismantle_below_price: u32,
+}
+
+/// Which potions we keep active. A character has three potion slots, the
+/// first three enabled types in this order get them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PotionSettings {
+    /// The potion of the class main attribute, e.g. intelligence for mages
+    pub main_attribute: bool,
+    pub constitution: bool,
+    pub luck: bool,
+    pub eternal_life: bool,
+    /// Replace potions that run out within this many minutes
+    pub renew_before_mins: i64,
+    /// Buy potions that cost mushrooms
+    pub mushrooms: bool,
+    /// Most mushrooms paid for a single potion
+    pub max_mushrooms: u32,
+    /// Silver (in copper, 100 = 1 silver coin) we never spend on potions
+    pub silver_reserve: u64,
+}
+
+impl Default for PotionSettings {
+    fn default() -> Self {
+        PotionSettings {
+            main_attribute: true,
+            constitution: true,
+            luck: true,
+            eternal_life: false,
+            renew_before_mins: 60,
+            mushrooms: false,
+            max_mushrooms: 1,
+            silver_reserve: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShopSettings {
+    /// How much better than the best item we own for its slot (worn, in the
+    /// bag or in the fortress chest) a shop item has to score to be bought
+    /// (0.1 = 10% better)
+    pub min_gain: f64,
+    /// Silver (in copper, 100 = 1 silver coin) we never spend on gear
+    pub silver_reserve: u64,
+}
+
+impl Default for ShopSettings {
+    fn default() -> Self {
+        ShopSettings {
+            min_gain: 0.1,
+            silver_reserve: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SimulationSettings {
+    /// Fights simulated per opponent before we pick one
+    pub iterations: u32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        SimulationSettings { iterations: 1000 }
+    }
+}
+
+/// What a quest is judged by, always per second of thirst it costs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum QuestObjective {
+    Xp,
+    Gold,
+    /// `xp_weight * xp + gold_weight * gold`
+    Mixed,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuestSettings {
+    pub objective: QuestObjective,
+    pub xp_weight: f64,
+    pub gold_weight: f64,
+    /// Added to the score (as a fraction, 0.25 = +25%) of quests that reward
+    /// an item better than the one we have equipped
+    pub item_bonus: f64,
+}
+
+impl Default for QuestSettings {
+    fn default() -> Self {
+        QuestSettings {
+            objective: QuestObjective::Xp,
+            xp_weight: 1.0,
+            gold_weight: 1.0,
+            item_bonus: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BeerSettings {
+    /// How many beers we are willing to buy on a normal day
+    pub max_beer: u8,
+    /// How many beers we are willing to buy while a quest/xp event is running
+    pub max_beer_event: u8,
+    /// Hours of city guard to start once we are out of thirst
+    pub city_guard_hours: u8,
+}
+
+impl Default for BeerSettings {
+    fn default() -> Self {
+        BeerSettings {
+            max_beer: 0,
+            max_beer_event: 10,
+            city_guard_hours: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DungeonSettings {
+    /// Mushrooms we need to have before we spend any of them on dungeons
+    pub mushroom_min: u32,
+    /// Only spend mushrooms if the simulated win chance (0.0 - 1.0) is at
+    /// least this high
+    pub mushroom_min_win_rate: f64,
+    /// Also fight in the shadow dungeons we have unlocked
+    pub shadow: bool,
+    /// Shadow dungeons are only chosen with at least this win chance
+    pub shadow_min_win_rate: f64,
+    /// The portal enemy keeps the damage of lost fights, so by default we
+    /// fight it every day no matter the simulated chance
+    pub portal_min_win_rate: f64,
+}
+
+impl Default for DungeonSettings {
+    fn default() -> Self {
+        DungeonSettings {
+            mushroom_min: 1000,
+            mushroom_min_win_rate: 0.8,
+            shadow: true,
+            shadow_min_win_rate: 0.5,
+            portal_min_win_rate: 0.0,
+        }
+    }
+}
+
+/// Item scoring weights. The main attribute of the character class gets
+/// `main`, the other two damage attributes `other`. Setting `strength`,
+/// `dexterity` or `intelligence` overrides that for the given attribute
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EquipSettings {
+    pub main: u32,
+    pub other: u32,
+    pub strength: Option<u32>,
+    pub dexterity: Option<u32>,
+    pub intelligence: Option<u32>,
+    pub constitution: u32,
+    pub luck: u32,
+    pub armor_weapon: u32,
+    /// Flat bonus for an enchanted item
+    pub enchantment: u32,
+    /// Bonus per upgrade level of an item
+    pub upgrade: u32,
+}
+
+impl Default for EquipSettings {
+    fn default() -> Self {
+        EquipSettings {
+            main: 5,
+            other: 1,
+            strength: None,
+            dexterity: None,
+            intelligence: None,
+            constitution: 4,
+            luck: 2,
+            armor_weapon: 6,
+            enchantment: 50,
+            upgrade: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExpeditionSettings {
+    /// Rewards we pick first, in the order given here
+    pub reward_priority: Vec<RewardKind>,
+    /// Expedition targets we start first, in the order given here
+    pub preferred_targets: Vec<ExpeditionTarget>,
+}
+
+impl Default for ExpeditionSettings {
+    fn default() -> Self {
+        ExpeditionSettings {
+            reward_priority: vec![
+                RewardKind::LuckyCoins,
+                RewardKind::Mushrooms,
+                RewardKind::Stone,
+                RewardKind::Wood,
+                RewardKind::QuicksandGlass,
+                RewardKind::Silver,
+            ],
+            preferred_targets: Vec::new(),
+        }
+    }
+}
+
+/// The expedition rewards that can be named in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum RewardKind {
+    LuckyCoins,
+    Mushrooms,
+    Stone,
+    Wood,
+    QuicksandGlass,
+    Silver,
+}
+
+impl From<RewardKind> for RewardType {
+    fn from(kind: RewardKind) -> Self {
+        match kind {
+            RewardKind::LuckyCoins => RewardType::LuckyCoins,
+            RewardKind::Mushrooms => RewardType::Mushrooms,
+            RewardKind::Stone => RewardType::Stone,
+            RewardKind::Wood => RewardType::Wood,
+            RewardKind::QuicksandGlass => RewardType::QuicksandGlass,
+            RewardKind::Silver => RewardType::Silver,
+        }
+    }
+}
+
+/// The expedition targets that can be named in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ExpeditionTarget {
+    ToiletPaper,
+    Dragon,
+    Cake,
+    RoyalFrog,
+    BurntCampfire,
+    WinnersPodium,
+    BrokenSword,
+    Klaus,
+    Unicorn,
+    Balloons,
+    RevealingCouple,
+}
+
+impl From<ExpeditionTarget> for ExpeditionThing {
+    fn from(target: ExpeditionTarget) -> Self {
+        match target {
+            ExpeditionTarget::ToiletPaper => ExpeditionThing::ToiletPaper,
+            ExpeditionTarget::Dragon => ExpeditionThing::Dragon,
+            ExpeditionTarget::Cake => ExpeditionThing::Cake,
+            ExpeditionTarget::RoyalFrog => ExpeditionThing::RoyalFrog,
+            ExpeditionTarget::BurntCampfire => ExpeditionThing::BurntCampfire,
+            ExpeditionTarget::WinnersPodium => ExpeditionThing::WinnersPodium,
+            ExpeditionTarget::BrokenSword => ExpeditionThing::BrokenSword,
+            ExpeditionTarget::Klaus => ExpeditionThing::Klaus,
+            ExpeditionTarget::Unicorn => ExpeditionThing::Unicorn,
+            ExpeditionTarget::Balloons => ExpeditionThing::Balloons,
+            ExpeditionTarget::RevealingCouple => ExpeditionThing::RevealingCouple,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FortressSettings {
+    /// Buildings we upgrade in this order. We save up for the first one that
+    /// is neither maxed nor held back by the fortress level. Buildings not
+    /// listed are never upgraded
+    pub build_order: Vec<FortressBuilding>,
+    /// Upgrade the first affordable building of the order instead of saving
+    /// up for the first one. Cheap buildings can then starve expensive ones
+    pub skip_unaffordable: bool,
+    /// Silver (in copper, 100 = 1 silver coin) we never spend on buildings
+    pub silver_reserve: u64,
+    /// Train soldiers, archers and mages up to the capacity of their building
+    pub train_units: bool,
+    /// Start gem searches and collect the found gems
+    pub gem_mine: bool,
+    /// Socket gems from the bag into our equipment
+    pub socket_gems: bool,
+    /// Also replace socketed gems with better ones. The old gem is lost
+    pub replace_gems: bool,
+}
+
+impl Default for FortressSettings {
+    fn default() -> Self {
+        FortressSettings {
+            build_order: vec![
+                FortressBuilding::Fortress,
+                FortressBuilding::WoodcutterGuild,
+                FortressBuilding::Quarry,
+                FortressBuilding::LaborersQuarters,
+                FortressBuilding::Academy,
+                FortressBuilding::Treasury,
+                FortressBuilding::GemMine,
+            ],
+            skip_unaffordable: false,
+            silver_reserve: 100_000,
+            train_units: false,
+            gem_mine: false,
+            socket_gems: false,
+            replace_gems: false,
+        }
+    }
+}
+
+/// The fortress buildings that can be named in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum FortressBuilding {
+    Fortress,
+    LaborersQuarters,
+    WoodcutterGuild,
+    Quarry,
+    GemMine,
+    Academy,
+    ArcheryGuild,
+    Barracks,
+    MagesTower,
+    Treasury,
+    Smithy,
+    Wall,
+}
+
+impl From<FortressBuilding> for FortressBuildingType {
+    fn from(building: FortressBuilding) -> Self {
+        match building {
+            FortressBuilding::Fortress => FortressBuildingType::Fortress,
+            FortressBuilding::LaborersQuarters => FortressBuildingType::LaborersQuarters,
+            FortressBuilding::WoodcutterGuild => FortressBuildingType::WoodcutterGuild,
+            FortressBuilding::Quarry => FortressBuildingType::Quarry,
+            FortressBuilding::GemMine => FortressBuildingType::GemMine,
+            FortressBuilding::Academy => FortressBuildingType::Academy,
+            FortressBuilding::ArcheryGuild => FortressBuildingType::ArcheryGuild,
+            FortressBuilding::Barracks => FortressBuildingType::Barracks,
+            FortressBuilding::MagesTower => FortressBuildingType::MagesTower,
+            FortressBuilding::Treasury => FortressBuildingType::Treasury,
+            FortressBuilding::Smithy => FortressBuildingType::Smithy,
+            FortressBuilding::Wall => FortressBuildingType::Wall,
+        }
+    }
+}
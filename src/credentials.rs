@@ -0,0 +1,101 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use rpassword::read_password;
+use sf_api::error::SFError;
+use sf_api::SimpleSession;
+use crate::config::{AccountConfig, Config};
+
+/// Login data for one account, either an S&F SSO account or a classic
+/// character on a single server
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    /// Set for classic logins, `None` means SSO
+    pub server: Option<String>,
+}
+
+impl Credentials {
+    /// Every account this process should play: the main account (SSO or
+    /// classic) and all `[[servers]]` entries. The main account is skipped if
+    /// only server entries are configured.
+    pub fn all(config: &Config) -> Result<Vec<Credentials>, String> {
+        let mut all: Vec<Credentials> = config
+            .servers
+            .iter()
+            .map(|s| Credentials {
+                username: s.username.clone(),
+                password: s.password.clone(),
+                server: Some(s.server.clone()),
+            })
+            .collect();
+
+        let main_configured = env::var("SF_USERNAME").is_ok()
+            || env::var("SF_CHARACTER").is_ok()
+            || config.account.username.is_some()
+            || config.account.character.is_some();
+        if main_configured || all.is_empty() {
+            all.insert(0, Credentials::resolve(&config.account)?);
+        }
+        Ok(all)
+    }
+
+    /// Collects the credentials from `SF_USERNAME`, `SF_PASSWORD`,
+    /// `SF_SERVER` and `SF_CHARACTER` (a `.env` file works too), then from the
+    /// `[account]` section of the config. Anything still missing is asked for
+    /// on the terminal, but only if there is someone to ask.
+    pub fn resolve(account: &AccountConfig) -> Result<Credentials, String> {
+        let server = env::var("SF_SERVER").ok().or_else(|| account.server.clone());
+        let character = env::var("SF_CHARACTER").ok().or_else(|| account.character.clone());
+
+        // Classic logins use the character name as the username
+        let username = character
+            .filter(|_| server.is_some())
+            .or_else(|| env::var("SF_USERNAME").ok())
+            .or_else(|| account.username.clone());
+        let username = match username {
+            Some(username) => username,
+            None => prompt("Enter your username: ")?,
+        };
+        let password = match env::var("SF_PASSWORD").ok().or_else(|| account.password.clone()) {
+            Some(password) => password,
+            None => prompt_password("Enter your password: ")?,
+        };
+
+        Ok(Credentials { username, password, server })
+    }
+
+    pub async fn login(&self) -> Result<Vec<SimpleSession>, SFError> {
+        match &self.server {
+            Some(server) => {
+                let session = SimpleSession::login(&self.username, &self.password, server).await?;
+                Ok(vec![session])
+            }
+            None => SimpleSession::login_sf_account(&self.username, &self.password).await,
+        }
+    }
+}
+
+fn ensure_tty(what: &str) -> Result<(), String> {
+    if io::stdin().is_terminal() {
+        Ok(())
+    } else {
+        Err(format!("No {} configured and stdin is not a terminal", what))
+    }
+}
+
+fn prompt(message: &str) -> Result<String, String> {
+    ensure_tty("username")?;
+    print!("{}", message);
+    io::stdout().flush().map_err(|e| e.to_string())?; // Ensures the prompt appears immediately
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).map_err(|e| e.to_string())?;
+    Ok(input.trim().to_string())
+}
+
+fn prompt_password(message: &str) -> Result<String, String> {
+    ensure_tty("password")?;
+    print!("{}", message);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    read_password().map_err(|e| e.to_string())
+}
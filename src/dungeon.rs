@@ -1,69 +1,116 @@
 use strum::IntoEnumIterator;
 use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::time::Duration;
 
 use sf_api::command::Command;
 use sf_api::command::Command::FightDungeon;
-use sf_api::gamestate::dungeons::{Dungeon, LightDungeon};
+use sf_api::gamestate::dungeons::{Dungeon, LightDungeon, ShadowDungeon};
 use sf_api::SimpleSession;
 use sf_api::simulate::Monster;
 use tokio::time::sleep;
+use crate::config::BotSettings;
+use crate::error::{BotError, BotResult};
 use crate::functions::{log_to_file, sell_the_worst_item, time_remaining};
+use crate::portal::{is_finished, record_progress};
+use crate::simulation::{win_rate_vs_monster, win_rate_vs_portal};
 
 pub struct Dungeons<'a> {
     session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
 }
 impl<'a> Dungeons<'a> {
     // Accepts a mutable reference to SimpleSession
-    pub fn new(session: &'a mut SimpleSession) -> Self {
-        Dungeons { session }
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Dungeons { session, settings }
     }
-    pub async fn do_dungeons(&mut self)-> Result<(), Box<dyn std::error::Error>>  {
-
+    pub async fn do_dungeons(&mut self)-> BotResult<()>  {
+        // Simulating a fight is expensive and the enemies only change when we
+        // beat them, so every enemy is simulated once per call
+        let mut win_rates = HashMap::new();
 
         loop {
             sleep(Duration::from_secs(2)).await;
-            let gs = self.session.send_command(Command::Update).await.unwrap();
+            let gs = self.session.send_command(Command::Update).await?;
 
             // We might have dungeon keys still waiting to be unlocked, so we
             // should use everything we have
             if let Some(unlockable) = gs.pending_unlocks.first().copied() {
                 self.session
                     .send_command(Command::UnlockFeature { unlockable })
-                    .await
-                    .unwrap();
+                    .await?;
                 continue;
             }
 
-            if let Some(portal) = &gs.dungeons.portal {
-                // TODO: I do not have a char, that has finished the portal, so you
-                // should maybe check the finished count against the current
-                if portal.can_fight {
-                    println!("Fighting the player portal");
-                    self.session.send_command(Command::FightPortal).await.unwrap();
+            let iterations = self.settings.simulation.iterations;
+            if let Some(portal) = gs.dungeons.portal.as_ref().filter(|p| p.can_fight && !is_finished(p)) {
+                let win_rate = win_rate_vs_portal(gs, iterations);
+                let character = gs.character.name.clone();
+                let enemy = portal.current;
+                if win_rate.map_or(true, |rate| rate >= self.settings.dungeons.portal_min_win_rate) {
+                    log_to_file(&format!(
+                        "Fighting portal enemy {} ({} estimated win chance)",
+                        enemy, win_rate.map_or("unknown".to_string(), |r| format!("{:.1}%", r * 100.0))
+                    )).await?;
+                    self.session.send_command(Command::FightPortal).await?;
+                    if let Some(portal) = self.session.game_state().and_then(|gs| gs.dungeons.portal.clone()) {
+                        record_progress(&character, &portal).await?;
+                    }
                     continue;
                 }
+                log_to_file(&format!("Skipping portal enemy {}, the estimated win chance is too low", enemy)).await?;
             }
 
             if gs.character.inventory.free_slot().is_none() {
-                sell_the_worst_item(self.session).await.expect("Error while selling item");
+                match sell_the_worst_item(self.session, self.settings).await {
+                    Err(BotError::InventoryFull) => {
+                        log_to_file("The bag is full of items worth keeping, dungeons wait until a slot is free").await?;
+                    }
+                    result => result?,
+                }
                 break;
             }
 
-            let mut best: Option<(Dungeon, &'static Monster)> = None;
-            // TODO: ShadowDungeons
+            let mut best: Option<(Dungeon, &'static Monster, f64)> = None;
+            let mut consider = |dungeon: Dungeon, monster: &'static Monster, win_rate: f64| {
+                // On equal chances the lower level monster is the safer bet
+                let is_better = best.map_or(true, |(_, old, old_rate)| {
+                    win_rate > old_rate || (win_rate == old_rate && monster.level < old.level)
+                });
+                if is_better {
+                    best = Some((dungeon, monster, win_rate))
+                }
+            };
             for l in LightDungeon::iter() {
                 let Some(current) = gs.dungeons.current_enemy(l) else {
                     continue;
                 };
-                // You should make a better heuristic to find these, but for now we
-                // just find the lowest level
-                if best.map_or(true, |old| old.1.level > current.level) {
-                    best = Some((l.into(), current))
+                // Our companions only fight with us in the tower
+                let companions = l == LightDungeon::Tower;
+                let win_rate = *win_rates
+                    .entry((Dungeon::from(l), current.level))
+                    .or_insert_with(|| win_rate_vs_monster(gs, current, iterations, companions));
+                consider(l.into(), current, win_rate);
+            }
+            if self.settings.dungeons.shadow {
+                for s in ShadowDungeon::iter() {
+                    let Some(current) = gs.dungeons.current_enemy(s) else {
+                        continue;
+                    };
+                    // Shadow monsters hit a lot harder than their level suggests
+                    // and a lost fight still costs the timer, so we only go
+                    // there if we are reasonably sure to win
+                    let win_rate = *win_rates
+                        .entry((Dungeon::from(s), current.level))
+                        .or_insert_with(|| win_rate_vs_monster(gs, current, iterations, false));
+                    if win_rate < self.settings.dungeons.shadow_min_win_rate {
+                        continue;
+                    }
+                    consider(s.into(), current, win_rate);
                 }
             }
 
-            let Some((target_dungeon, target_monster)) = best
+            let Some((target_dungeon, target_monster, win_rate)) = best
             else {
                 println!("There are no more enemies left to fight");
                 break;
@@ -79,22 +126,23 @@ impl<'a> Dungeons<'a> {
 
 
             if rem > Duration::from_secs(60 * 5)
-                && gs.character.mushrooms > 1000
-                && target_monster.level <= gs.character.level + 20
+                && gs.character.mushrooms > self.settings.dungeons.mushroom_min
+                && win_rate >= self.settings.dungeons.mushroom_min_win_rate
             {
-                // You should add some better logic on when to skip this
-                log_to_file("Using mushrooms to fight in the dungeon").await?;
+                log_to_file(&format!(
+                    "Using mushrooms to fight level {} in {:?} ({:.1}% estimated win chance)",
+                    target_monster.level, target_dungeon, win_rate * 100.0
+                )).await?;
                 self.session
                     .send_command(Command::FightDungeon {
                         dungeon: target_dungeon,
                         use_mushroom: true,
                     })
-                    .await
-                    .unwrap();
+                    .await?;
             }
             if rem < Duration::from_secs(1) {
                 self.session.send_command(FightDungeon { dungeon: target_dungeon, use_mushroom: false }).await?;
-                log_to_file(&format!("Chose: {:?} as the best dungeon to fight in", target_dungeon)).await?;
+                log_to_file(&format!("Chose: {:?} as the best dungeon to fight in ({:.1}% estimated win chance)", target_dungeon, win_rate * 100.0)).await?;
             }else{
                 log_to_file(&format!("{:?} minutes until the next dungeon fight is available", rem/60)).await?;
                 break;
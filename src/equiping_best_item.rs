@@ -1,142 +1,334 @@
-use sf_api::command::Command;
-use sf_api::gamestate::items::{Item, ItemType};
+use sf_api::command::{AttributeType, Command};
+use sf_api::gamestate::character::Class;
+use sf_api::gamestate::dungeons::CompanionClass;
+use sf_api::gamestate::items::{EquipmentSlot, Gem, GemSlot, GemType, Item, ItemType, PlayerItemPlace};
+use sf_api::gamestate::GameState;
 use sf_api::misc::EnumMapGet;
 use sf_api::SimpleSession;
-use crate::functions::log_to_file;
+use strum::IntoEnumIterator;
+use crate::config::{BotSettings, EquipSettings};
+use crate::error::BotResult;
+use crate::functions::{log_to_file, main_attribute};
 
 pub struct Equip<'a> {
     session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
 }
 
 impl<'a> Equip<'a> {
     // Accepts a mutable reference to SimpleSession
-    pub fn new(session: &'a mut SimpleSession) -> Self {
-        Equip { session }
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Equip { session, settings }
     }
 
-    pub async fn equip(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut gs = self.session.send_command(Command::Update).await?;
-        let eq_items = gs.character.equipment.0.clone();  // This holds the equipment items
-        let backpack = gs.character.inventory.bag.clone();  // This holds the backpack items
-
-        for (eq_slot_index, eq_item_option) in eq_items.iter().enumerate() {
-            for (back_slot_index, back_item_option) in backpack.iter().enumerate() {
-                if let Some(back_item) = back_item_option {
-                    // Check if the equipment slot is empty
-                    if eq_item_option.1.is_none() {
-                        if format!("{:?}", back_item.typ) == format!("{:?}", eq_item_option.0) {
-                            let command = Command::InventoryMove {
-                                inventory_from: sf_api::gamestate::items::PlayerItemPlace::MainInventory,
-                                inventory_from_pos: back_slot_index,
-                                inventory_to: sf_api::gamestate::items::PlayerItemPlace::Equipment,
-                                inventory_to_pos: eq_slot_index,
-                            };
-                            self.session.send_command(command).await?;
-                            log_to_file(&format!("Equipped {:?} from backpack slot {} to equipment slot {:?}", back_item.typ, back_slot_index, eq_item_option.0)).await?;
+    /// Dry run: prints the score of every equipped and every bag item without
+    /// moving anything
+    pub async fn print_score_table(&mut self) -> BotResult<()> {
+        let gs = self.session.send_command(Command::Update).await?;
+        let weights = ItemWeights::for_class(gs.character.class, &self.settings.equip);
+        println!("{} ({:?}), weights {:?}", gs.character.name, gs.character.class, weights);
+        println!("{:<12} {:<28} {:>10}", "place", "item", "score");
+        for (slot, item) in gs.character.equipment.0.iter() {
+            match item {
+                Some(item) => println!("{:<12} {:<28} {:>10.1}", format!("{:?}", slot), format!("{:?}", item.typ), item_score(item, &weights)),
+                None => println!("{:<12} {:<28} {:>10}", format!("{:?}", slot), "-", "-"),
+            }
+        }
+        for (pos, item) in gs.character.inventory.bag.iter().enumerate() {
+            let Some(item) = item else {
+                continue;
+            };
+            let score = item_score(item, &weights);
+            let equipped = equipped_counterpart(gs, item).map_or(0.0, |e| item_score(&e, &weights));
+            println!(
+                "{:<12} {:<28} {:>10.1} ({:+.1} vs equipped)",
+                format!("bag {}", pos), format!("{:?}", item.typ), score, score - equipped
+            );
+        }
+        Ok(())
+    }
+
+    /// Repeatedly moves the single best upgrade from the bag or the fortress
+    /// chest into our equipment, re-reading the inventory after every move
+    /// since each one shifts items around
+    pub async fn equip(&mut self) -> BotResult<()> {
+        for _ in 0..MAX_MOVES {
+            let gs = self.session.send_command(Command::Update).await?;
+            let weights = ItemWeights::for_class(gs.character.class, &self.settings.equip);
+
+            let mut best: Option<(PlayerItemPlace, usize, EquipmentSlot, f64)> = None;
+            for (place, items) in item_sources(gs) {
+                for (pos, item) in items.iter().enumerate() {
+                    let Some(item) = item else {
+                        continue;
+                    };
+                    let Some(slot) = equipment_slot(&item.typ) else {
+                        continue;
+                    };
+                    if !can_wear(item, gs.character.class) {
+                        continue;
+                    }
+                    let current = gs.character.equipment.0.get(slot).as_ref().map_or(0.0, |c| item_score(c, &weights));
+                    let gain = item_score(item, &weights) - current;
+                    if gain > 0.0 && best.map_or(true, |(_, _, _, best_gain)| gain > best_gain) {
+                        best = Some((place, pos, slot, gain));
+                    }
+                }
+            }
+
+            let Some((place, pos, slot, gain)) = best else {
+                break;
+            };
+            let command = Command::InventoryMove {
+                inventory_from: place,
+                inventory_from_pos: pos,
+                inventory_to: PlayerItemPlace::Equipment,
+                inventory_to_pos: slot_index(slot),
+            };
+            self.session.send_command(command).await?;
+            log_to_file(&format!("Equipped {:?} slot {} into {:?} (score {:+.1})", place, pos, slot, gain)).await?;
+        }
+        Ok(())
+    }
+
+    /// Gives bag and fortress chest items to the tower companion that gains
+    /// the most from them. Runs after `equip`, so the main character had the
+    /// first pick already
+    pub async fn equip_companions(&mut self) -> BotResult<()> {
+        for _ in 0..MAX_MOVES {
+            let gs = self.session.send_command(Command::Update).await?;
+            let Some(companions) = &gs.dungeons.companions else {
+                // The tower is not unlocked yet
+                return Ok(());
+            };
+
+            let mut best: Option<(PlayerItemPlace, usize, CompanionClass, EquipmentSlot, f64)> = None;
+            for (place, items) in item_sources(gs) {
+                for (pos, item) in items.iter().enumerate() {
+                    let Some(item) = item else {
+                        continue;
+                    };
+                    let Some(slot) = equipment_slot(&item.typ) else {
+                        continue;
+                    };
+                    for (comp_class, companion) in companions.iter() {
+                        let class = companion_class(comp_class);
+                        if !can_wear(item, class) {
+                            continue;
                         }
-                    } else if let Some(current_item) = eq_item_option.1.as_ref() {
-                        match (current_item.typ, &back_item.typ) {
-                            (ItemType::Weapon { .. }, ItemType::Weapon { .. }) => {
-                                if is_better_item(back_item.clone(), Some(current_item.clone())).await {
-                                    let command = Command::InventoryMove {
-                                        inventory_from: sf_api::gamestate::items::PlayerItemPlace::MainInventory,
-                                        inventory_from_pos: back_slot_index,
-                                        inventory_to: sf_api::gamestate::items::PlayerItemPlace::Equipment,
-                                        inventory_to_pos: eq_slot_index,
-                                    };
-                                    if let Err(e) = self.session.send_command(command).await {
-                                        log_to_file(&format!("Failed to move item: {:?}", e)).await?;
-                                    } else {
-                                        log_to_file(&format!("Replaced {:?} in equipment slot {} with better {:?}", eq_item_option.0, eq_slot_index, back_item.typ)).await?;
-                                    }
-                                } else {
-                                }
-                            },
-                            _ if current_item.typ == back_item.typ => {
-                                // For non-weapon items, just check if types are equal
-                                if is_better_item(back_item.clone(), Some(current_item.clone())).await {
-                                    log_to_file(&format!("New item is better for slot {:?}", eq_item_option.0)).await?;
-                                    let command = Command::InventoryMove {
-                                        inventory_from: sf_api::gamestate::items::PlayerItemPlace::MainInventory,
-                                        inventory_from_pos: back_slot_index,
-                                        inventory_to: sf_api::gamestate::items::PlayerItemPlace::Equipment,
-                                        inventory_to_pos: eq_slot_index,
-                                    };
-                                    if let Err(e) = self.session.send_command(command).await {
-                                        log_to_file(&format!("Failed to move item: {:?}", e)).await?;
-                                    } else {
-                                        log_to_file(&format!("Replaced {:?} in equipment slot {} with better {:?}", eq_item_option.0, eq_slot_index, back_item.typ)).await?;
-                                    }
-                                } else {
-                                }
-                            },
-                            _ => {
-                            }
+                        let weights = ItemWeights::for_class(class, &self.settings.equip);
+                        let current = companion.equipment.0.get(slot).as_ref().map_or(0.0, |c| item_score(c, &weights));
+                        let gain = item_score(item, &weights) - current;
+                        if gain > 0.0 && best.map_or(true, |(_, _, _, _, best_gain)| gain > best_gain) {
+                            best = Some((place, pos, comp_class, slot, gain));
                         }
                     }
                 }
             }
+
+            let Some((place, pos, companion, slot, gain)) = best else {
+                break;
+            };
+            self.session.send_command(Command::EquipCompanion {
+                from_inventory: place,
+                from_pos: pos,
+                to_companion: companion,
+                to_slot: slot_index(slot),
+            }).await?;
+            log_to_file(&format!("Gave {:?} slot {} to the {:?} companion as {:?} (score {:+.1})", place, pos, companion, slot, gain)).await?;
+        }
+        Ok(())
+    }
+
+    /// Puts gems from the bag into the equipped item where they add the most.
+    /// Empty sockets are always filled, socketed gems are only replaced with
+    /// `replace_gems`, since the old gem is lost
+    pub async fn socket_gems(&mut self) -> BotResult<()> {
+        let replace = self.settings.fortress.replace_gems;
+        for _ in 0..MAX_MOVES {
+            let gs = self.session.send_command(Command::Update).await?;
+            let weights = ItemWeights::for_class(gs.character.class, &self.settings.equip);
+
+            let mut best: Option<(usize, EquipmentSlot, f64)> = None;
+            for (pos, item) in gs.character.inventory.bag.iter().enumerate() {
+                let Some(Item { typ: ItemType::Gem(gem), .. }) = item else {
+                    continue;
+                };
+                let value = gem_score(gem, &weights);
+                for (slot, equipped) in gs.character.equipment.0.iter() {
+                    let current = match equipped.as_ref().and_then(|e| e.gem_slot.as_ref()) {
+                        Some(GemSlot::Empty) => 0.0,
+                        Some(GemSlot::Filled(old)) if replace => gem_score(old, &weights),
+                        _ => continue,
+                    };
+                    let gain = value - current;
+                    if gain > 0.0 && best.map_or(true, |(_, _, best_gain)| gain > best_gain) {
+                        best = Some((pos, slot, gain));
+                    }
+                }
+            }
+
+            let Some((pos, slot, gain)) = best else {
+                break;
+            };
+            self.session.send_command(Command::InventoryMove {
+                inventory_from: PlayerItemPlace::MainInventory,
+                inventory_from_pos: pos,
+                inventory_to: PlayerItemPlace::Equipment,
+                inventory_to_pos: slot_index(slot),
+            }).await?;
+            log_to_file(&format!("Socketed the gem from bag slot {} into the {:?} (score {:+.1})", pos, slot, gain)).await?;
         }
         Ok(())
     }
 }
 
-fn get_attribute_value(item: Item, attr_type: sf_api::command::AttributeType) -> u32 {
-    *item.attributes.get(attr_type)
+/// Safety net against swapping two items back and forth forever
+const MAX_MOVES: usize = 20;
+
+fn companion_class(companion: CompanionClass) -> Class {
+    match companion {
+        CompanionClass::Warrior => Class::Warrior,
+        CompanionClass::Mage => Class::Mage,
+        CompanionClass::Scout => Class::Scout,
+    }
 }
 
-async fn is_better_item(new_item: Item, current_item: Option<Item>) -> bool {
-    let strength_weight = 5;
-    let constitution_weight = 4;
-    let luck_weight = 2;
-    let intelligence_weight = 1;
-    let dexterity_weight = 1;
-    let armor_weapon_weight = 6;
-
-    // Calculate scores based on weighted attribute values
-    let new_score = calculate_attribute_score(&new_item, strength_weight, constitution_weight, luck_weight, intelligence_weight, dexterity_weight)
-        + new_item.armor() * armor_weapon_weight; // Armor for non-weapons
-
-    let current_score = if let Some(ref current) = current_item {
-        calculate_attribute_score(current, strength_weight, constitution_weight, luck_weight, intelligence_weight, dexterity_weight)
-            + current.armor() * armor_weapon_weight
-    } else {
-        0
-    };
+/// The places we take items from: the bag and, if unlocked, the fortress chest
+pub(crate) fn item_sources(gs: &GameState) -> Vec<(PlayerItemPlace, &[Option<Item>])> {
+    let mut sources = vec![(PlayerItemPlace::MainInventory, &gs.character.inventory.bag[..])];
+    if let Some(chest) = &gs.character.inventory.fortress_chest {
+        sources.push((PlayerItemPlace::ExtendedInventory, &chest[..]));
+    }
+    sources
+}
 
-    if new_item.typ.is_weapon() {
-        // Extract min and max damage for weapons
-        if let ItemType::Weapon { min_dmg, max_dmg } = new_item.typ {
-            let new_avg_dmg = (min_dmg + max_dmg) as f64 / 2.0; // Calculate average damage
-            let new_dmg_score = new_avg_dmg * armor_weapon_weight as f64; // Weight the damage
-
-            let current_avg_dmg = if let Some(current) = current_item.as_ref() {
-                if let ItemType::Weapon { min_dmg, max_dmg } = current.typ {
-                    (min_dmg + max_dmg) as f64 / 2.0
-                } else {
-                    0.0
-                }
-            } else {
-                0.0
-            };
-            let current_dmg_score = current_avg_dmg * armor_weapon_weight as f64;
+/// The equipment slot an item goes into, `None` for things like potions
+pub(crate) fn equipment_slot(typ: &ItemType) -> Option<EquipmentSlot> {
+    Some(match typ {
+        ItemType::Hat => EquipmentSlot::Hat,
+        ItemType::BreastPlate => EquipmentSlot::BreastPlate,
+        ItemType::Gloves => EquipmentSlot::Gloves,
+        ItemType::FootWear => EquipmentSlot::FootWear,
+        ItemType::Weapon { .. } => EquipmentSlot::Weapon,
+        ItemType::Shield { .. } => EquipmentSlot::Shield,
+        ItemType::Amulet => EquipmentSlot::Amulet,
+        ItemType::Belt => EquipmentSlot::Belt,
+        ItemType::Ring => EquipmentSlot::Ring,
+        ItemType::Talisman => EquipmentSlot::Talisman,
+        _ => return None,
+    })
+}
+
+/// Position of a slot in the equipment, as the server expects it in moves
+pub(crate) fn slot_index(slot: EquipmentSlot) -> usize {
+    EquipmentSlot::iter().position(|s| s == slot).unwrap_or_default()
+}
+
+/// Items that are made for another class can not be equipped
+pub(crate) fn can_wear(item: &Item, class: Class) -> bool {
+    item.class.map_or(true, |c| c == class)
+}
+
+/// The item we currently wear in the place the given item would go
+pub(crate) fn equipped_counterpart(gs: &GameState, item: &Item) -> Option<Item> {
+    let slot = equipment_slot(&item.typ)?;
+    gs.character.equipment.0.get(slot).clone()
+}
+
+/// How much one point of each item property is worth to a character
+#[derive(Debug, Clone)]
+pub(crate) struct ItemWeights {
+    pub strength: u32,
+    pub dexterity: u32,
+    pub intelligence: u32,
+    pub constitution: u32,
+    pub luck: u32,
+    pub armor_weapon: u32,
+    pub enchantment: u32,
+    pub upgrade: u32,
+}
 
-            let new_total_score = new_score as f64 + new_dmg_score;
-            let current_total_score = current_score as f64 + current_dmg_score;
+impl ItemWeights {
+    /// The class main attribute gets the `main` weight, the other two damage
+    /// attributes the `other` one. Explicit weights in the config win
+    pub fn for_class(class: Class, settings: &EquipSettings) -> Self {
+        let main = main_attribute(class);
+        let derived = |attr: AttributeType| if attr == main { settings.main } else { settings.other };
+        ItemWeights {
+            strength: settings.strength.unwrap_or_else(|| derived(AttributeType::Strength)),
+            dexterity: settings.dexterity.unwrap_or_else(|| derived(AttributeType::Dexterity)),
+            intelligence: settings.intelligence.unwrap_or_else(|| derived(AttributeType::Intelligence)),
+            constitution: settings.constitution,
+            luck: settings.luck,
+            armor_weapon: settings.armor_weapon,
+            enchantment: settings.enchantment,
+            upgrade: settings.upgrade,
+        }
+    }
 
-            return new_total_score > current_total_score;
+    pub fn attribute(&self, attr: AttributeType) -> u32 {
+        match attr {
+            AttributeType::Strength => self.strength,
+            AttributeType::Dexterity => self.dexterity,
+            AttributeType::Intelligence => self.intelligence,
+            AttributeType::Constitution => self.constitution,
+            AttributeType::Luck => self.luck,
         }
     }
+}
+
+fn get_attribute_value(item: &Item, attr_type: AttributeType) -> u32 {
+    *item.attributes.get(attr_type)
+}
+
+const ATTRIBUTES: [AttributeType; 5] = [
+    AttributeType::Strength,
+    AttributeType::Dexterity,
+    AttributeType::Intelligence,
+    AttributeType::Constitution,
+    AttributeType::Luck,
+];
+
+/// Weighted value of an item: attributes, armor or average weapon damage,
+/// the socketed gem, the enchantment and the upgrade level
+pub(crate) fn item_score(item: &Item, weights: &ItemWeights) -> f64 {
+    let attributes: u32 = ATTRIBUTES
+        .iter()
+        .map(|&attr| weights.attribute(attr) * get_attribute_value(item, attr))
+        .sum();
+    let mut score = attributes as f64 + (item.armor() * weights.armor_weapon) as f64; // Armor for non-weapons
+
+    if let ItemType::Weapon { min_dmg, max_dmg } = item.typ {
+        let avg_dmg = (min_dmg + max_dmg) as f64 / 2.0; // Calculate average damage
+        score += avg_dmg * weights.armor_weapon as f64;
+    }
 
-    // For non-weapon items
+    if let Some(GemSlot::Filled(gem)) = &item.gem_slot {
+        score += gem_score(gem, weights);
+    }
+    if item.enchantment.is_some() {
+        score += weights.enchantment as f64;
+    }
+    score += (item.upgrade_count as u32 * weights.upgrade) as f64;
+    score
+}
 
-    new_score > current_score
+/// Weighted value of a gem, socketed or not
+fn gem_score(gem: &Gem, weights: &ItemWeights) -> f64 {
+    let gem_weight = match gem.typ {
+        GemType::Strength => weights.strength,
+        GemType::Dexterity => weights.dexterity,
+        GemType::Intelligence => weights.intelligence,
+        GemType::Constitution => weights.constitution,
+        GemType::Luck => weights.luck,
+        // These raise every attribute at once
+        GemType::All | GemType::Legendary => ATTRIBUTES.iter().map(|&a| weights.attribute(a)).sum(),
+    };
+    (gem.value * gem_weight) as f64
 }
 
-fn calculate_attribute_score(item: &Item, strength_weight: u32, constitution_weight: u32, luck_weight: u32, intelligence_weight: u32, dexterity_weight: u32) -> u32 {
-    (strength_weight * get_attribute_value(item.clone(), sf_api::command::AttributeType::Strength))
-        + (constitution_weight * get_attribute_value(item.clone(), sf_api::command::AttributeType::Constitution))
-        + (luck_weight * get_attribute_value(item.clone(), sf_api::command::AttributeType::Luck))
-        + (intelligence_weight * get_attribute_value(item.clone(), sf_api::command::AttributeType::Intelligence))
-        + (dexterity_weight * get_attribute_value(item.clone(), sf_api::command::AttributeType::Dexterity))
-}
\ No newline at end of file
+pub(crate) fn is_better_item(new_item: Item, current_item: Option<Item>, weights: &ItemWeights) -> bool {
+    let current_score = current_item.as_ref().map_or(0.0, |current| item_score(current, weights));
+    item_score(&new_item, weights) > current_score
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use sf_api::error::SFError;
+
+pub type BotResult<T> = Result<T, BotError>;
+
+/// Parts of the errors the server answers with once a session is no longer
+/// valid. The server is not consistent about case and spacing
+const SESSION_ERRORS: [&str; 3] = ["sessionid invalid", "session id invalid", "session expired"];
+
+fn is_session_error(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    SESSION_ERRORS.iter().any(|e| msg.contains(e))
+}
+
+#[derive(Debug)]
+pub enum BotError {
+    /// The server could not be reached or sent something we could not read
+    Network(SFError),
+    /// The server no longer accepts our session
+    SessionExpired(String),
+    /// The server understood the command, but refused it
+    ServerRejected(String),
+    /// The game state did not look like we expected it to
+    UnexpectedState(String),
+    /// There is no free slot to put a new item into
+    InventoryFull,
+    /// Writing the log or reading the config failed
+    Io(std::io::Error),
+    Config(String),
+}
+
+/// What the main loop should do after an activity failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Probably temporary, try the same activity again
+    Retry,
+    /// Log it and go on with the next activity
+    Skip,
+    /// The session is gone, log in again before doing anything else
+    Relogin,
+}
+
+impl BotError {
+    pub fn unexpected(msg: impl Into<String>) -> Self {
+        BotError::UnexpectedState(msg.into())
+    }
+
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            // Only a lost connection is worth another try, a response we
+            // could not parse will look the same the next time
+            BotError::Network(SFError::ConnectionError) => Recovery::Retry,
+            BotError::SessionExpired(_) => Recovery::Relogin,
+            BotError::Network(_)
+            | BotError::ServerRejected(_)
+            | BotError::UnexpectedState(_)
+            | BotError::InventoryFull
+            | BotError::Io(_)
+            | BotError::Config(_) => Recovery::Skip,
+        }
+    }
+}
+
+impl fmt::Display for BotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BotError::Network(e) => write!(f, "network error: {:?}", e),
+            BotError::SessionExpired(msg) => write!(f, "session expired: {}", msg),
+            BotError::ServerRejected(msg) => write!(f, "server rejected the command: {}", msg),
+            BotError::UnexpectedState(msg) => write!(f, "unexpected game state: {}", msg),
+            BotError::InventoryFull => write!(f, "inventory is full"),
+            BotError::Io(e) => write!(f, "io error: {}", e),
+            BotError::Config(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BotError {}
+
+impl From<SFError> for BotError {
+    fn from(e: SFError) -> Self {
+        match e {
+            SFError::ServerError(msg) if is_session_error(&msg) => BotError::SessionExpired(msg),
+            SFError::ServerError(msg) => BotError::ServerRejected(msg),
+            e => BotError::Network(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for BotError {
+    fn from(e: std::io::Error) -> Self {
+        BotError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for BotError {
+    fn from(e: toml::de::Error) -> Self {
+        BotError::Config(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_errors_need_a_relogin() {
+        for msg in ["sessionid invalid", "Sessionid Invalid", " session id invalid ", "Session expired"] {
+            let e = BotError::from(SFError::ServerError(msg.to_string()));
+            assert!(matches!(e, BotError::SessionExpired(_)), "{}", msg);
+            assert_eq!(e.recovery(), Recovery::Relogin);
+        }
+    }
+
+    #[test]
+    fn other_server_errors_are_skipped() {
+        let e = BotError::from(SFError::ServerError("cannot do this".to_string()));
+        assert!(matches!(e, BotError::ServerRejected(_)));
+        assert_eq!(e.recovery(), Recovery::Skip);
+    }
+
+    #[test]
+    fn lost_connections_are_retried() {
+        let e = BotError::from(SFError::ConnectionError);
+        assert!(matches!(e, BotError::Network(SFError::ConnectionError)));
+        assert_eq!(e.recovery(), Recovery::Retry);
+        assert_eq!(BotError::InventoryFull.recovery(), Recovery::Skip);
+    }
+}
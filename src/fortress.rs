@@ -0,0 +1,215 @@
+use chrono::Local;
+use sf_api::command::Command;
+use sf_api::gamestate::fortress::{Fortress as FortressState, FortressBuildingType, FortressCost, FortressResourceType, FortressUnitType};
+use sf_api::gamestate::GameState;
+use sf_api::misc::EnumMapGet;
+use sf_api::SimpleSession;
+use strum::IntoEnumIterator;
+use crate::config::BotSettings;
+use crate::equiping_best_item::Equip;
+use crate::error::{BotResult, Recovery};
+use crate::functions::{log_to_file, time_remaining};
+
+pub struct Fortress<'a> {
+    session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
+}
+
+impl<'a> Fortress<'a> {
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Fortress { session, settings }
+    }
+
+    /// Collects what the production buildings made, starts the next building
+    /// upgrade of the build order we can afford, and, if enabled, trains
+    /// units, runs the gem mine and sockets the gems it found. A failing step
+    /// does not keep the others from running
+    pub async fn manage(&mut self) -> BotResult<()> {
+        let settings = self.settings;
+        step_done("collecting", self.collect_resources().await).await?;
+        step_done("building", self.upgrade_building().await).await?;
+        if settings.fortress.train_units {
+            step_done("training", self.train_units().await).await?;
+        }
+        if settings.fortress.gem_mine {
+            step_done("gem mine", self.dig_gems().await).await?;
+        }
+        if settings.fortress.socket_gems {
+            step_done("socketing", Equip::new(self.session, settings).socket_gems().await).await?;
+        }
+        Ok(())
+    }
+
+    async fn collect_resources(&mut self) -> BotResult<()> {
+        for resource in FortressResourceType::iter() {
+            let gs = self.session.send_command(Command::Update).await?;
+            let Some(fortress) = &gs.fortress else {
+                // The fortress is not unlocked yet
+                return Ok(());
+            };
+            let collectable = fortress.resources.get(resource).production.last_collectable;
+            if collectable == 0 {
+                continue;
+            }
+            self.session.send_command(Command::FortressGather { resource }).await?;
+            log_to_file(&format!("Collected {} {:?} in the fortress", collectable, resource)).await?;
+        }
+        Ok(())
+    }
+
+    /// Only one building can be upgraded at a time. We save up for the first
+    /// building of the build order that can still be upgraded, unless
+    /// `skip_unaffordable` lets cheaper buildings further down go first
+    async fn upgrade_building(&mut self) -> BotResult<()> {
+        let settings = &self.settings.fortress;
+        let gs = self.session.send_command(Command::Update).await?;
+        let Some(fortress) = &gs.fortress else {
+            return Ok(());
+        };
+        if let Some(building) = fortress.building_upgrade.target {
+            log_to_file(&format!("The fortress is still upgrading the {:?}", building)).await?;
+            return Ok(());
+        }
+
+        let budget = Budget::of(gs, fortress, settings.silver_reserve);
+        let affordable = |b: FortressBuildingType| budget.times(&fortress.buildings.get(b).upgrade_cost) > 0;
+        let mut upgradable = settings.build_order
+            .iter()
+            .map(|b| FortressBuildingType::from(*b))
+            .filter(|b| can_upgrade(fortress, *b));
+        let next = if settings.skip_unaffordable {
+            upgradable.find(|b| affordable(*b))
+        } else {
+            match upgradable.next() {
+                Some(b) if !affordable(b) => {
+                    log_to_file(&format!("Saving up for the {:?} upgrade", b)).await?;
+                    return Ok(());
+                }
+                next => next,
+            }
+        };
+
+        let Some(f_type) = next else {
+            return Ok(());
+        };
+        let level = fortress.buildings.get(f_type).level;
+        self.session.send_command(Command::FortressBuild { f_type }).await?;
+        log_to_file(&format!("Started upgrading the {:?} to level {}", f_type, level + 1)).await?;
+        Ok(())
+    }
+
+    /// Fills the barracks, archery guild and mages tower up to their capacity,
+    /// or as far as the resources go without touching the silver reserve
+    async fn train_units(&mut self) -> BotResult<()> {
+        for unit in FortressUnitType::iter() {
+            let gs = self.session.send_command(Command::Update).await?;
+            let Some(fortress) = &gs.fortress else {
+                return Ok(());
+            };
+            let state = fortress.units.get(unit);
+            let missing = (state.limit as u32).saturating_sub(state.count as u32 + state.in_training as u32);
+            if missing == 0 {
+                continue;
+            }
+            let budget = Budget::of(gs, fortress, self.settings.fortress.silver_reserve);
+            let count = missing.min(budget.times(&state.training_cost).min(u32::MAX as u64) as u32);
+            if count == 0 {
+                log_to_file(&format!("Not enough resources to train {:?} units", unit)).await?;
+                continue;
+            }
+            self.session.send_command(Command::FortressBuildUnit { unit, count }).await?;
+            log_to_file(&format!("Started training {} {:?} units", count, unit)).await?;
+        }
+        Ok(())
+    }
+
+    /// Collects the gem of a finished search and starts the next one. The gem
+    /// needs a free bag slot, otherwise it stays in the mine until next time
+    async fn dig_gems(&mut self) -> BotResult<()> {
+        let gs = self.session.send_command(Command::Update).await?;
+        let Some(fortress) = &gs.fortress else {
+            return Ok(());
+        };
+        if fortress.buildings.get(FortressBuildingType::GemMine).level == 0 {
+            return Ok(());
+        }
+
+        match fortress.gem_search.finish {
+            Some(finish) if finish > Local::now() => {
+                log_to_file(&format!("{:?} minutes until the gem search is done", time_remaining(finish).as_secs() / 60)).await?;
+                return Ok(());
+            }
+            Some(_) => {
+                if gs.character.inventory.free_slot().is_none() {
+                    log_to_file("No free bag slot for the found gem").await?;
+                    return Ok(());
+                }
+                self.session.send_command(Command::FortressGemStoneSearchFinish { mushrooms: 0 }).await?;
+                log_to_file("Collected a gem from the gem mine").await?;
+            }
+            None => {}
+        }
+
+        // The response of the finished search may hold newer resources
+        let gs = self.session.send_command(Command::Update).await?;
+        let Some(fortress) = &gs.fortress else {
+            return Ok(());
+        };
+        let budget = Budget::of(gs, fortress, self.settings.fortress.silver_reserve);
+        if budget.times(&fortress.gem_search.cost) == 0 {
+            log_to_file("Not enough resources for a new gem search").await?;
+            return Ok(());
+        }
+        self.session.send_command(Command::FortressGemStoneSearch).await?;
+        log_to_file("Started a new gem search").await?;
+        Ok(())
+    }
+}
+
+/// Logs the error of a failed step, so the next one can still run. Errors
+/// worth a retry or a new session end the fortress pass, so the retry policy
+/// of the activity can handle them
+async fn step_done(step: &str, result: BotResult<()>) -> BotResult<()> {
+    match result {
+        Err(e) if e.recovery() == Recovery::Skip => log_to_file(&format!("Fortress {} failed: {}", step, e)).await,
+        result => result,
+    }
+}
+
+/// Resources the fortress may spend. Silver below the reserve is left alone
+struct Budget {
+    wood: u64,
+    stone: u64,
+    silver: u64,
+}
+
+impl Budget {
+    fn of(gs: &GameState, fortress: &FortressState, silver_reserve: u64) -> Self {
+        Budget {
+            wood: fortress.resources.get(FortressResourceType::Wood).current,
+            stone: fortress.resources.get(FortressResourceType::Stone).current,
+            silver: gs.character.silver.saturating_sub(silver_reserve),
+        }
+    }
+
+    /// How often we can pay `cost`
+    fn times(&self, cost: &FortressCost) -> u64 {
+        [(cost.wood, self.wood), (cost.stone, self.stone), (cost.silver, self.silver)]
+            .into_iter()
+            .filter(|(price, _)| *price > 0)
+            .map(|(price, have)| have / price)
+            .min()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+/// Whether a building has a next level we are allowed to build. Maxed
+/// buildings have no upgrade cost, and no building may outgrow the fortress
+fn can_upgrade(fortress: &FortressState, building: FortressBuildingType) -> bool {
+    let cost = &fortress.buildings.get(building).upgrade_cost;
+    if cost.wood == 0 && cost.stone == 0 && cost.silver == 0 {
+        return false;
+    }
+    building == FortressBuildingType::Fortress
+        || fortress.buildings.get(building).level < fortress.buildings.get(FortressBuildingType::Fortress).level
+}
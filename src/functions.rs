@@ -2,12 +2,17 @@ use std::borrow::Borrow;
 use std::fs::OpenOptions;
 use std::time::Duration;
 use chrono::{DateTime, Local};
-use sf_api::command::Command;
-use sf_api::gamestate::items::PlayerItemPlace;
+use sf_api::command::{AttributeType, Command};
+use sf_api::gamestate::character::Class;
+use sf_api::gamestate::items::{GemSlot, Item, PlayerItemPlace};
+use sf_api::gamestate::GameState;
 use sf_api::SimpleSession;
 use std::io::Write;
+use crate::config::BotSettings;
+use crate::equiping_best_item::{can_wear, equipment_slot, equipped_counterpart, item_score, ItemWeights};
+use crate::error::{BotError, BotResult};
 
-pub async fn log_to_file(message: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn log_to_file(message: &str) -> BotResult<()> {
     let now = Local::now();
     let timestamp = now.format("%Y-%m-%d %H:%M:%S").to_string();
 
@@ -25,23 +30,123 @@ pub async fn log_to_file(message: &str) -> Result<(), Box<dyn std::error::Error>
 
     Ok(())
 }
+/// The attribute that makes a class hit harder
+pub fn main_attribute(class: Class) -> AttributeType {
+    match class {
+        Class::Mage | Class::Druid | Class::Bard | Class::Necromancer => AttributeType::Intelligence,
+        Class::Scout | Class::Assassin | Class::DemonHunter => AttributeType::Dexterity,
+        _ => AttributeType::Strength,
+    }
+}
+
 pub fn time_remaining<T: Borrow<DateTime<Local>>>(time: T) -> Duration {
     (*time.borrow() - Local::now()).to_std().unwrap_or_default()
 }
-pub async fn sell_the_worst_item(mut session: &mut SimpleSession) -> Result<(), Box<dyn std::error::Error>> {
+/// Frees a bag slot by getting rid of the least useful item. Epic items,
+/// socketed items, items that beat what we wear, items missing from our
+/// scrapbook and everything that is not equipment (keys, potions, gems, ...)
+/// are never touched. Cheap items are dismantled instead of sold, if the
+/// config says their resources are worth more than the silver. If every item
+/// is worth keeping, one is moved to the fortress chest instead. Only if that
+/// is full as well this fails with `InventoryFull`
+pub async fn sell_the_worst_item(session: &mut SimpleSession, settings: &BotSettings) -> BotResult<()> {
     let gs = session.send_command(Command::Update).await?;
-    let backpack = gs.character.inventory.bag.clone();
-    let mut bad_item_index = 0;
-    let mut bad_valu = 999999999;
-    for (back_slot_index, back_item_option) in backpack.iter().enumerate() {
-        if !back_item_option.is_none(){
-            if bad_valu > back_item_option.clone().unwrap().price{
-                bad_item_index = back_slot_index;
-                bad_valu = back_item_option.clone().unwrap().price;
-            }
+    let weights = ItemWeights::for_class(gs.character.class, &settings.equip);
+
+    let mut worst: Option<(usize, f64, u32)> = None;
+    for (pos, item) in gs.character.inventory.bag.iter().enumerate() {
+        let Some(item) = item else {
+            continue;
+        };
+        if !is_sellable(gs, item) {
+            continue;
+        }
+        let usefulness = item_usefulness(gs, item, &weights);
+        if usefulness > 0.0 && can_wear(item, gs.character.class) {
+            continue;
+        }
+        if is_worse(worst, usefulness, item.price) {
+            worst = Some((pos, usefulness, item.price));
         }
     }
-    session.send_command(Command::SellShop { inventory: PlayerItemPlace::MainInventory, inventory_pos: bad_item_index }).await?;
-    log_to_file(&format!("Sold an item on index {:?}", bad_item_index)).await?;
+
+    let Some((bad_item_index, _, price)) = worst else {
+        return store_in_chest(session, &weights).await;
+    };
+    if price < settings.selling.dismantle_below_price {
+        session.send_command(Command::BlacksmithDismantle { inventory_t: PlayerItemPlace::MainInventory, position: bad_item_index }).await?;
+        log_to_file(&format!("Dismantled an item on index {:?} instead of selling it for {}", bad_item_index, price)).await?;
+    } else {
+        session.send_command(Command::SellShop { inventory: PlayerItemPlace::MainInventory, inventory_pos: bad_item_index }).await?;
+        log_to_file(&format!("Sold an item on index {:?} for {}", bad_item_index, price)).await?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// How much better (or worse) an item is than what we wear
+fn item_usefulness(gs: &GameState, item: &Item, weights: &ItemWeights) -> f64 {
+    let equipped = equipped_counterpart(gs, item).map_or(0.0, |e| item_score(&e, weights));
+    item_score(item, weights) - equipped
+}
+
+/// Whether an item ranks below the current worst one: less useful, or as
+/// useful and cheaper
+fn is_worse(worst: Option<(usize, f64, u32)>, usefulness: f64, price: u32) -> bool {
+    worst.map_or(true, |(_, worst_use, worst_price)| {
+        usefulness < worst_use || (usefulness == worst_use && price < worst_price)
+    })
+}
+
+/// Moves the least useful equipment item of the bag (or any item, if there
+/// is no equipment) to a free slot of the fortress chest
+async fn store_in_chest(session: &mut SimpleSession, weights: &ItemWeights) -> BotResult<()> {
+    let gs = session.send_command(Command::Update).await?;
+    let Some(chest_pos) = gs.character.inventory.fortress_chest
+        .as_ref()
+        .and_then(|chest| chest.iter().position(Option::is_none))
+    else {
+        return Err(BotError::InventoryFull);
+    };
+    let bag = &gs.character.inventory.bag;
+    let mut worst: Option<(usize, f64, u32)> = None;
+    for (pos, item) in bag.iter().enumerate() {
+        let Some(item) = item.as_ref().filter(|i| equipment_slot(&i.typ).is_some()) else {
+            continue;
+        };
+        let usefulness = item_usefulness(gs, item, weights);
+        if is_worse(worst, usefulness, item.price) {
+            worst = Some((pos, usefulness, item.price));
+        }
+    }
+    let Some(bag_pos) = worst
+        .map(|(pos, _, _)| pos)
+        .or_else(|| bag.iter().position(Option::is_some))
+    else {
+        return Err(BotError::InventoryFull);
+    };
+    session.send_command(Command::InventoryMove {
+        inventory_from: PlayerItemPlace::MainInventory,
+        inventory_from_pos: bag_pos,
+        inventory_to: PlayerItemPlace::ExtendedInventory,
+        inventory_to_pos: chest_pos,
+    }).await?;
+    log_to_file(&format!("Every item is worth keeping, moved the least useful one on index {} to the fortress chest", bag_pos)).await?;
+    Ok(())
+}
+
+fn is_sellable(gs: &GameState, item: &Item) -> bool {
+    if equipment_slot(&item.typ).is_none() || item.is_epic() {
+        return false;
+    }
+    if matches!(item.gem_slot, Some(GemSlot::Filled(_))) {
+        return false;
+    }
+    // An item we do not have in our scrapbook yet is worth more there
+    let new_for_scrapbook = gs
+        .character
+        .scrapbook
+        .as_ref()
+        .zip(item.equipment_ident())
+        .map_or(false, |(book, ident)| !book.items.contains(&ident));
+    !new_for_scrapbook
+}
@@ -0,0 +1,30 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::error::BotResult;
+
+/// Appends one entry as a JSON line to the given file
+pub fn append<T: Serialize>(file: &str, entry: &T) -> BotResult<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(file)?;
+    let line = serde_json::to_string(entry).map_err(std::io::Error::from)?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+/// Reads every entry of a JSON lines file. A missing file is just an empty
+/// history and lines we can not parse (e.g. from an older version) are skipped
+pub fn read_all<T: DeserializeOwned>(file: &str) -> BotResult<Vec<T>> {
+    let file = match OpenOptions::new().read(true).open(file) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
@@ -6,14 +6,27 @@ mod dungeon;
 mod functions;
 mod arena;
 mod Expedision;
+mod config;
+mod credentials;
+mod scheduler;
+mod sessions;
+mod error;
+mod tavern;
+mod simulation;
+mod history;
+mod portal;
+mod arena_history;
+mod attributes;
+mod shop;
+mod potions;
+mod fortress;
 
+use std::path::PathBuf;
 use std::time::Duration;
 use sf_api::SimpleSession;
 use tokio;
 use tokio::time::sleep;
-use crate::quest::Questing;
-use std::io::{self, Write}; // For reading input from the user
-use rpassword::read_password;
+use std::io;
 use sf_api::command::Command;
 use crate::equiping_best_item::Equip;
 use log::{error, info, warn};
@@ -21,9 +34,18 @@ use chrono::Local;
 use fern::Dispatch;
 use sf_api::gamestate::tavern::CurrentAction::Expedition;
 use crate::arena::Arena;
+use crate::attributes::Attributes;
+use crate::shop::Shopping;
+use crate::potions::Potions;
+use crate::fortress::Fortress;
 use crate::dungeon::Dungeons;
-use crate::Expedision::Exping;
-use crate::functions::{log_to_file, sell_the_worst_item};
+use crate::config::{BotSettings, Config};
+use crate::credentials::Credentials;
+use crate::scheduler::{Activity, Schedule};
+use crate::sessions::login_all;
+use crate::tavern::{DailyPlan, Tavern};
+use crate::error::{BotError, BotResult, Recovery};
+use crate::functions::{log_to_file, sell_the_worst_item, time_remaining};
 
 fn setup_logger() -> Result<(), fern::InitError> {
     // Create a dispatch for logging
@@ -53,73 +75,200 @@ async fn main() {
     // Initialize logging
     setup_logger().expect("Failed to initialize logger");
 
-    // Prompt the user for the username
-    print!("Enter your username: ");
-    io::stdout().flush().unwrap(); // Ensures the prompt appears immediately
-    let mut username = String::new();
-    io::stdin().read_line(&mut username).unwrap();
-    let username = username.trim(); // Removes any trailing newline
-
-    // Prompt the user for the password
-    print!("Enter your password: ");
-    io::stdout().flush().unwrap(); // Ensures the password prompt is displayed
-    let password = read_password().expect("Failed to read password");
-    // Attempt to log in with the provided credentials
-
-    info!("Starting main loop...");
-    loop {
-        let mut sessions = match SimpleSession::login_sf_account(username, &password).await {
-            Ok(s) => {
-                info!("Logged in successfully.");
-                s
+    let config_path = config_path_from_args();
+    let config = if config_path.exists() {
+        match Config::load(&config_path) {
+            Ok(c) => {
+                info!("Loaded config from {}", config_path.display());
+                c
             }
             Err(e) => {
-                error!("Login failed: {:?}", e);
+                error!("Could not read config {}: {}", config_path.display(), e);
                 return;
             }
-        };
-
-        for session in &mut sessions {
-            session.send_command(Command::Update).await.expect("Failed to update");
-            log_to_file(&*session.game_state_mut().unwrap().character.name.clone()).await.expect("Failed to log to file");
-            log_to_file("{").await.expect("Failed to log to file");
+        }
+    } else {
+        warn!("No config found at {}, using the defaults", config_path.display());
+        Config::default()
+    };
 
-            if  session.game_state().unwrap().character.inventory.free_slot().is_none(){
-                sell_the_worst_item(session).await.expect("Cannot sell item");
-            }
-            for item in session.game_state().unwrap().character.inventory.bag.clone(){
+    let _ = dotenv::dotenv();
+    let accounts = match Credentials::all(&config) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Missing credentials: {}", e);
+            return;
+        }
+    };
 
-            }
+    let idle_retry = Duration::from_secs(config.sleep_secs);
 
+    // We keep these sessions for as long as the server accepts them
+    let mut accounts = login_all(accounts).await;
+    if accounts.iter().all(|a| a.characters.is_empty()) {
+        warn!("No account could be logged in yet, retrying in the main loop");
+    }
 
-            let mut equip = Equip::new(session);
-            if let Err(e) = equip.equip().await {
-                error!("Failed to equip items: {:?}", e);
+    if std::env::args().any(|a| a == "--equip-scores") {
+        // Dry run, only show how the items would be judged
+        for character in accounts.iter_mut().flat_map(|a| a.characters.iter_mut()) {
+            let settings = config.for_character(&character.name);
+            if let Err(e) = Equip::new(&mut character.session, settings).print_score_table().await {
+                error!("Could not score the items of {}: {}", character.name, e);
             }
-            /*
-            let mut quest = Questing::new(session);
-            if let Err(e) = quest.questing().await {
-                error!("Questing failed: {:?}", e);
-            }
-            */
-            let mut exp =Exping::new(session);
-            if let Err(e) = exp.Exping().await {
-                error!("Failed to start expedition: {:?}", e);
+        }
+        return;
+    }
+
+    info!("Starting main loop...");
+    loop {
+        for account in accounts.iter_mut() {
+            if account.needs_login() {
+                account.login().await;
             }
-            let mut dungeon = Dungeons::new(session);
-            if let Err(e) = dungeon.do_dungeons().await {
-                error!("Dungeon failed: {:?}", e);
+            for character in account.characters.iter_mut() {
+                if character.needs_login() || !character.schedule.any_due(Local::now()) {
+                    continue;
+                }
+                let name = character.name.clone();
+                info!("Handling {}", name);
+                let settings = config.for_character(&name);
+                if let Err(e) = run_character(&mut character.session, settings, &character.schedule, &mut character.tavern_plan).await {
+                    // One broken character should not keep the others from playing
+                    error!("Cycle for {} failed: {}", name, e);
+                    character.check_error(&e);
+                }
+                match character.session.send_command(Command::Update).await {
+                    Ok(gs) => {
+                        // Characters can be renamed, and the settings are
+                        // looked up by name
+                        character.name = gs.character.name.clone();
+                        character.schedule.update(gs, idle_retry);
+                    }
+                    Err(e) => {
+                        let e = BotError::from(e);
+                        error!("Could not refresh {} for scheduling: {}", name, e);
+                        character.check_error(&e);
+                    }
+                }
             }
+        }
+
+        // Sleep until the first activity of any character is actionable, or
+        // until we may try to log a rejected account in again
+        let wait = accounts
+            .iter()
+            .flat_map(|a| {
+                let logins = a.next_login();
+                let wakeups = a.characters.iter().filter_map(|c| c.schedule.next_wakeup());
+                logins.into_iter().chain(wakeups)
+            })
+            .min()
+            .map(time_remaining)
+            .unwrap_or(idle_retry)
+            .min(idle_retry);
+        info!("Sleeping {}s until the next activity is due", wait.as_secs());
+        sleep(wait + Duration::from_secs(1)).await;
+    }
+}
 
-            let mut arena = Arena::new(session);
-            if let Err(e) = arena.fight_arena().await {
-                error!("Dungeon failed: {:?}", e);
+/// Reads the value of `--config <path>`, falling back to `config.toml`
+fn config_path_from_args() -> PathBuf {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            if let Some(path) = args.next() {
+                return PathBuf::from(path);
             }
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return PathBuf::from(path);
+        }
+    }
+    PathBuf::from("config.toml")
+}
 
-            log_to_file("}").await.expect("Failed to log to file");
-            break;
+/// Delay before the first retry of an activity. Doubled for every further one
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Applies the recovery policy to the result of one activity. Returns `true`
+/// if the activity should be run again, which happens at most `max_retries`
+/// times and after a short backoff. Errors that need a new session are passed
+/// on, so the rest of the cycle is skipped.
+async fn retry_activity(name: &str, activity: &str, max_retries: u32, attempt: &mut u32, result: BotResult<()>) -> BotResult<bool> {
+    let Err(e) = result else {
+        return Ok(false);
+    };
+    match e.recovery() {
+        Recovery::Retry if *attempt < max_retries => {
+            *attempt += 1;
+            warn!("[{}] {} failed, retrying ({}/{}): {}", name, activity, attempt, max_retries, e);
+            sleep(RETRY_BASE_DELAY.saturating_mul(2u32.saturating_pow(*attempt - 1))).await;
+            Ok(true)
+        }
+        Recovery::Relogin => Err(e),
+        _ => {
+            error!("[{}] {} failed, skipping it: {}", name, activity, e);
+            Ok(false)
         }
-        sleep(Duration::from_secs(60)).await;
+    }
+}
+
+async fn run_character(
+    session: &mut SimpleSession,
+    settings: &BotSettings,
+    schedule: &Schedule,
+    tavern_plan: &mut DailyPlan,
+) -> BotResult<()> {
+    let now = Local::now();
+    let gs = session.send_command(Command::Update).await?;
+    let name = gs.character.name.clone();
+    let bag_full = gs.character.inventory.free_slot().is_none();
+    log_to_file(&name).await?;
+    log_to_file("{").await?;
+
+    if bag_full {
+        let mut attempt = 0;
+        while retry_activity(&name, "Selling", settings.retries.selling, &mut attempt, sell_the_worst_item(session, settings).await).await? {}
+    }
 
+    // Bought items only end up in the bag, the equip pass puts them on
+    if settings.activities.shopping {
+        let mut attempt = 0;
+        while retry_activity(&name, "Shopping", settings.retries.shopping, &mut attempt, Shopping::new(session, settings).buy_gear().await).await? {}
     }
+    if settings.activities.equip {
+        let mut attempt = 0;
+        while retry_activity(&name, "Equipping", settings.retries.equip, &mut attempt, Equip::new(session, settings).equip().await).await? {}
+    }
+    if settings.activities.companions {
+        let mut attempt = 0;
+        while retry_activity(&name, "Companions", settings.retries.companions, &mut attempt, Equip::new(session, settings).equip_companions().await).await? {}
+    }
+    if settings.activities.attributes {
+        let mut attempt = 0;
+        while retry_activity(&name, "Attributes", settings.retries.attributes, &mut attempt, Attributes::new(session, settings).level_attributes().await).await? {}
+    }
+    if settings.activities.potions {
+        let mut attempt = 0;
+        while retry_activity(&name, "Potions", settings.retries.potions, &mut attempt, Potions::new(session, settings).keep_active().await).await? {}
+    }
+    if settings.activities.tavern && schedule.is_due(Activity::Tavern, now) {
+        let mut attempt = 0;
+        while retry_activity(&name, "Tavern", settings.retries.tavern, &mut attempt, Tavern::new(session, settings, tavern_plan).run().await).await? {}
+    }
+    if settings.activities.dungeons && schedule.is_due(Activity::Dungeons, now) {
+        let mut attempt = 0;
+        while retry_activity(&name, "Dungeon", settings.retries.dungeons, &mut attempt, Dungeons::new(session, settings).do_dungeons().await).await? {}
+    }
+    if settings.activities.arena && schedule.is_due(Activity::Arena, now) {
+        let mut attempt = 0;
+        while retry_activity(&name, "Arena", settings.retries.arena, &mut attempt, Arena::new(session, settings).fight_arena().await).await? {}
+    }
+    if settings.activities.fortress {
+        let mut attempt = 0;
+        while retry_activity(&name, "Fortress", settings.retries.fortress, &mut attempt, Fortress::new(session, settings).manage().await).await? {}
+    }
+
+    log_to_file("}").await?;
+    Ok(())
 }
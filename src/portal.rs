@@ -0,0 +1,100 @@
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sf_api::gamestate::dungeons::Portal;
+use crate::error::BotResult;
+use crate::functions::log_to_file;
+use crate::history;
+
+const PORTAL_HISTORY_FILE: &str = "portal_progress.jsonl";
+/// The player portal has this many enemies, after that it is finished
+pub const PORTAL_ENEMIES: u16 = 50;
+
+/// The state of the portal of one character after a fight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortalRecord {
+    pub character: String,
+    pub date: NaiveDate,
+    /// The enemy we are at, 1 based
+    pub current: u16,
+    pub finished: u16,
+    pub enemy_level: u32,
+    pub enemy_hp_percentage: u8,
+}
+
+pub fn is_finished(portal: &Portal) -> bool {
+    portal.finished >= PORTAL_ENEMIES
+}
+
+/// Stores the portal progress of today and logs how many more days the
+/// current enemy will probably take, based on the damage of earlier days
+pub async fn record_progress(character: &str, portal: &Portal) -> BotResult<()> {
+    let record = PortalRecord {
+        character: character.to_string(),
+        date: Local::now().date_naive(),
+        current: portal.current as u16,
+        finished: portal.finished,
+        enemy_level: portal.enemy_level,
+        enemy_hp_percentage: portal.enemy_hp_percentage,
+    };
+    history::append(PORTAL_HISTORY_FILE, &record)?;
+
+    let history: Vec<PortalRecord> = history::read_all(PORTAL_HISTORY_FILE)?;
+    let first_seen = history
+        .iter()
+        .find(|r| r.character == record.character && r.current == record.current);
+    let estimate = first_seen.and_then(|first| days_left(first, &record));
+    match estimate {
+        Some(days) => log_to_file(&format!(
+            "Portal enemy {} (level {}) is at {}% health, about {} more days to beat it",
+            record.current, record.enemy_level, record.enemy_hp_percentage, days
+        )).await,
+        None => log_to_file(&format!(
+            "Portal enemy {} (level {}) is at {}% health",
+            record.current, record.enemy_level, record.enemy_hp_percentage
+        )).await,
+    }
+}
+
+/// Days until the enemy reaches 0% health, extrapolated from the health lost
+/// between the first record of this enemy and the latest one
+fn days_left(first: &PortalRecord, latest: &PortalRecord) -> Option<u32> {
+    let days = (latest.date - first.date).num_days();
+    let lost = first.enemy_hp_percentage.saturating_sub(latest.enemy_hp_percentage) as f64;
+    if days <= 0 || lost <= 0.0 {
+        return None;
+    }
+    let per_day = lost / days as f64;
+    Some((latest.enemy_hp_percentage as f64 / per_day).ceil() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(date: NaiveDate, hp: u8) -> PortalRecord {
+        PortalRecord {
+            character: "Me".to_string(),
+            date,
+            current: 3,
+            finished: 2,
+            enemy_level: 200,
+            enemy_hp_percentage: hp,
+        }
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, d).unwrap()
+    }
+
+    #[test]
+    fn extrapolates_the_daily_damage() {
+        // 30% lost in 2 days, 55% left at 15% a day
+        assert_eq!(days_left(&record(day(1), 85), &record(day(3), 55)), Some(4));
+    }
+
+    #[test]
+    fn needs_at_least_a_day_and_some_damage() {
+        assert_eq!(days_left(&record(day(1), 80), &record(day(1), 60)), None);
+        assert_eq!(days_left(&record(day(1), 80), &record(day(4), 80)), None);
+    }
+}
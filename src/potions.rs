@@ -0,0 +1,150 @@
+use chrono::{Duration, Local};
+use sf_api::command::{AttributeType, Command};
+use sf_api::gamestate::items::{ItemType, PlayerItemPlace, Potion, PotionSize, PotionType};
+use sf_api::gamestate::unlockables::ShopType;
+use sf_api::gamestate::GameState;
+use sf_api::misc::EnumMapGet;
+use sf_api::SimpleSession;
+use crate::config::{BotSettings, PotionSettings};
+use crate::error::BotResult;
+use crate::functions::{log_to_file, main_attribute};
+
+/// A character can only have this many potions active at once
+const POTION_SLOTS: usize = 3;
+
+pub struct Potions<'a> {
+    session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
+}
+
+impl<'a> Potions<'a> {
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Potions { session, settings }
+    }
+
+    /// Renews every wanted potion that is missing or about to run out, with
+    /// one from the bag if we have it and otherwise from the magic shop
+    pub async fn keep_active(&mut self) -> BotResult<()> {
+        let gs = self.session.send_command(Command::Update).await?;
+        let wanted = wanted_potions(gs, &self.settings.potions);
+
+        for typ in wanted {
+            let gs = self.session.send_command(Command::Update).await?;
+            if !needs_renewal(gs, typ, self.settings.potions.renew_before_mins) {
+                continue;
+            }
+            let pos = match bag_potion(gs, typ) {
+                Some(pos) => pos,
+                None => match self.buy_potion(typ).await? {
+                    Some(pos) => pos,
+                    None => continue,
+                },
+            };
+            self.session.send_command(Command::UsePotion {
+                from: PlayerItemPlace::MainInventory,
+                pos,
+            }).await?;
+            log_to_file(&format!("Drank a {:?} potion", typ)).await?;
+        }
+        Ok(())
+    }
+
+    /// Buys the biggest potion of this type the magic shop offers and we are
+    /// willing to pay for. Returns the bag position it landed on
+    async fn buy_potion(&mut self, typ: PotionType) -> BotResult<Option<usize>> {
+        let settings = &self.settings.potions;
+        let gs = self.session.send_command(Command::Update).await?;
+        let Some(free_pos) = gs.character.inventory.bag.iter().position(Option::is_none) else {
+            log_to_file("No free bag slot to buy a potion into").await?;
+            return Ok(None);
+        };
+        let budget = gs.character.silver.saturating_sub(settings.silver_reserve);
+
+        let best = gs.shops.get(ShopType::Magic).items
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, item)| match &item.typ {
+                ItemType::Potion(potion) if potion.typ == typ => Some((pos, potion, item)),
+                _ => None,
+            })
+            .filter(|(_, _, item)| {
+                if item.mushroom_price > 0 {
+                    settings.mushrooms && item.mushroom_price <= settings.max_mushrooms
+                        && item.mushroom_price <= gs.character.mushrooms
+                } else {
+                    item.price as u64 <= budget
+                }
+            })
+            .max_by_key(|(_, potion, _)| size_rank(potion))
+            .map(|(pos, _, item)| (pos, item.price, item.mushroom_price));
+
+        let Some((shop_pos, price, mushroom_price)) = best else {
+            log_to_file(&format!("The magic shop has no affordable {:?} potion", typ)).await?;
+            return Ok(None);
+        };
+        self.session.send_command(Command::BuyShop {
+            shop_type: ShopType::Magic,
+            shop_pos,
+            inventory: PlayerItemPlace::MainInventory,
+            inventory_pos: free_pos,
+        }).await?;
+        if mushroom_price > 0 {
+            log_to_file(&format!("Bought a {:?} potion for {} mushrooms", typ, mushroom_price)).await?;
+        } else {
+            log_to_file(&format!("Bought a {:?} potion for {} silver", typ, price)).await?;
+        }
+        Ok(Some(free_pos))
+    }
+}
+
+/// The potion types we keep active, the one of the class main attribute
+/// first, cut to the number of potion slots
+fn wanted_potions(gs: &GameState, settings: &PotionSettings) -> Vec<PotionType> {
+    let main = match main_attribute(gs.character.class) {
+        AttributeType::Dexterity => PotionType::Dexterity,
+        AttributeType::Intelligence => PotionType::Intelligence,
+        _ => PotionType::Strength,
+    };
+    [
+        (main, settings.main_attribute),
+        (PotionType::Constitution, settings.constitution),
+        (PotionType::Luck, settings.luck),
+        (PotionType::EternalLife, settings.eternal_life),
+    ]
+        .into_iter()
+        .filter(|(_, wanted)| *wanted)
+        .map(|(typ, _)| typ)
+        .take(POTION_SLOTS)
+        .collect()
+}
+
+/// Whether no potion of this type is active or the active one runs out within
+/// `renew_before_mins`
+fn needs_renewal(gs: &GameState, typ: PotionType, renew_before_mins: i64) -> bool {
+    let deadline = Local::now() + Duration::minutes(renew_before_mins);
+    !gs.character.active_potions
+        .iter()
+        .flatten()
+        .any(|p| p.typ == typ && p.expires.map_or(true, |e| e > deadline))
+}
+
+/// Position of the biggest potion of this type in the bag
+fn bag_potion(gs: &GameState, typ: PotionType) -> Option<usize> {
+    gs.character.inventory.bag
+        .iter()
+        .enumerate()
+        .filter_map(|(pos, item)| match &item.as_ref()?.typ {
+            ItemType::Potion(potion) if potion.typ == typ => Some((pos, potion)),
+            _ => None,
+        })
+        .max_by_key(|(_, potion)| size_rank(potion))
+        .map(|(pos, _)| pos)
+}
+
+fn size_rank(potion: &Potion) -> u8 {
+    match potion.size {
+        PotionSize::Small => 0,
+        PotionSize::Medium => 1,
+        PotionSize::Large => 2,
+    }
+}
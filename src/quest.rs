@@ -6,7 +6,7 @@ use sf_api::{
     command::{Command, ExpeditionSetting, TimeSkip},
     gamestate::{
         items::{Enchantment, EquipmentSlot},
-        tavern::{AvailableTasks, CurrentAction},
+        tavern::{AvailableTasks, CurrentAction, Quest},
     },
     misc::EnumMapGet,
     SimpleSession,
@@ -17,35 +17,45 @@ use std::fs::OpenOptions;
 use std::io::Write;
 use sf_api::gamestate::items::PlayerItemPlace;
 use sf_api::gamestate::rewards::Event::{EpicQuestExtravaganza, ExceptionalXPEvent, OneBeerTwoBeerFreeBeer};
+use crate::config::{BotSettings, QuestObjective, QuestSettings};
+use crate::equiping_best_item::{equipped_counterpart, is_better_item, ItemWeights};
+use crate::error::{BotError, BotResult};
 use crate::functions::{log_to_file, sell_the_worst_item, time_remaining};
 
 pub struct Questing<'a> {
     session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
 }
 impl<'a> Questing<'a> {
     // Accepts a mutable reference to SimpleSession
-    pub fn new(session: &'a mut SimpleSession) -> Self {
-        Questing { session }
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Questing { session, settings }
     }
 
 
 
-    pub async fn questing(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn questing(&mut self) -> BotResult<()> {
         loop {
             sleep(Duration::from_secs(2)).await;
-            let gs = self.session.send_command(Command::Update).await.unwrap();
+            let gs = self.session.send_command(Command::Update).await?;
 
             match &gs.tavern.current_action {
                 CurrentAction::Idle => match gs.tavern.available_tasks() {
                     AvailableTasks::Quests(q) => {
-                        let mut best_quest_index = 0;
-                        let mut best_quest = gs.tavern.quests.first().unwrap().clone();
-                        for (index, quest) in gs.tavern.quests.clone().iter().enumerate() {
-                            if quest.base_experience > best_quest.base_experience {
-                                best_quest = quest.clone();
-                                best_quest_index = index;
+                        let mut best: Option<(usize, Quest, f64)> = None;
+                        for (index, quest) in gs.tavern.quests.iter().enumerate() {
+                            let score = quest_score(quest, gs, self.settings);
+                            if best.as_ref().map_or(true, |(_, _, s)| score > *s) {
+                                best = Some((index, quest.clone(), score));
                             }
                         }
+                        let (best_quest_index, best_quest, best_score) = best
+                            .ok_or_else(|| BotError::unexpected("Quests are available, but the tavern has none"))?;
+                        log_to_file(&format!(
+                            "Best quest is #{} with a {:?} score of {:.2} ({} xp, {} silver, {}s)",
+                            best_quest_index, self.settings.quests.objective, best_score,
+                            best_quest.base_experience, best_quest.base_silver, best_quest.base_length
+                        )).await?;
 
                         if best_quest.base_length > gs.tavern.thirst_for_adventure_sec {
                             let has_extra_beer = gs.character.equipment.has_enchantment(Enchantment::ThirstyWanderer);
@@ -56,27 +66,27 @@ impl<'a> Questing<'a> {
                             for event in events {
                                 if event == ExceptionalXPEvent || event == EpicQuestExtravaganza || event == OneBeerTwoBeerFreeBeer {
                                     let gs = self.session.send_command(Command::Update).await?;
-                                    if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (10 + has_extra_beer as u8) {
+                                    if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (self.settings.beer.max_beer_event + has_extra_beer as u8) {
                                         log_to_file("Buying beer").await?;
                                         self.session.send_command(Command::BuyBeer).await?;
                                         a = true;
                                         continue;
                                     } else {
                                         log_to_file("Starting city guard").await?;
-                                        self.session.send_command(Command::StartWork { hours: 10 }).await?;
+                                        self.session.send_command(Command::StartWork { hours: self.settings.beer.city_guard_hours }).await?;
                                         break;
                                     }
                                 }
                             }
                             if !a {
                                 let gs = self.session.send_command(Command::Update).await?;
-                                if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (0 + has_extra_beer as u8) {
+                                if gs.character.mushrooms > 0 && gs.tavern.beer_drunk < (self.settings.beer.max_beer + has_extra_beer as u8) {
                                     log_to_file("Buying beer").await?;
                                     self.session.send_command(Command::BuyBeer).await?;
                                     continue;
                                 } else {
                                     log_to_file("Starting city guard").await?;
-                                    self.session.send_command(Command::StartWork { hours: 10 }).await?;
+                                    self.session.send_command(Command::StartWork { hours: self.settings.beer.city_guard_hours }).await?;
                                     break;
                                 }
                             } else {
@@ -89,7 +99,13 @@ impl<'a> Questing<'a> {
                         log_to_file("Starting the next quest").await?;
 
                         if best_quest.item.is_some() && gs.character.inventory.free_slot().is_none() {
-                            sell_the_worst_item(self.session).await?;
+                            match sell_the_worst_item(self.session, self.settings).await {
+                                // Better to lose the quest item than to stop questing
+                                Err(BotError::InventoryFull) => {
+                                    log_to_file("The bag is full of items worth keeping, the quest item will be lost").await?;
+                                }
+                                result => result?,
+                            }
                         }
 
                         let q = self.session
@@ -101,17 +117,10 @@ impl<'a> Questing<'a> {
                         continue;
                     }
                     AvailableTasks::Expeditions(_) => {
-                        if !gs.tavern.can_change_questing_preference() {
-                            log_to_file("We cannot do quests because we have done expeditions today already").await?;
-                            break;
-                        }
-                        log_to_file("Changing questing setting").await?;
-                        self.session
-                            .send_command(Command::SetQuestsInsteadOfExpeditions {
-                                value: ExpeditionSetting::PreferQuests,
-                            })
-                            .await?;
-                        continue;
+                        // The questing preference is owned by the tavern
+                        // strategy, so we do not touch it here
+                        log_to_file("The tavern offers expeditions instead of quests").await?;
+                        break;
                     }
                 },
                 CurrentAction::Quest {
@@ -132,14 +141,15 @@ impl<'a> Questing<'a> {
                         log_to_file(&format!("Skipping the remaining {:?} with a {:?}", remaining, skip)).await?;
                         self.session
                             .send_command(Command::FinishQuest { skip: Some(skip) })
-                            .await
-                            .unwrap();
-                    } else {
-                        log_to_file(&format!("Waiting {:?} until the quest is finished", remaining)).await?;
-                        sleep(remaining).await;
+                            .await?;
+                    } else if remaining == Duration::ZERO {
                         self.session
                             .send_command(Command::FinishQuest { skip })
                             .await?;
+                    } else {
+                        // The scheduler wakes us up again once the quest is done
+                        log_to_file(&format!("{:?} until the quest is finished", remaining)).await?;
+                        break;
                     }
                 }
                 CurrentAction::CityGuard { hours, busy_until } => {
@@ -167,3 +177,28 @@ impl<'a> Questing<'a> {
 
 }
 
+/// Combines xp and gold per second of thirst as the configured objective asks
+pub fn objective_score(xp: f64, gold: f64, settings: &QuestSettings) -> f64 {
+    match settings.objective {
+        QuestObjective::Xp => xp,
+        QuestObjective::Gold => gold,
+        QuestObjective::Mixed => settings.xp_weight * xp + settings.gold_weight * gold,
+    }
+}
+
+/// Scores a quest by the configured objective per second of thirst. Quests
+/// with an item that beats our equipped one get a bonus on top
+fn quest_score(quest: &Quest, gs: &GameState, settings: &BotSettings) -> f64 {
+    let secs = quest.base_length.max(1) as f64;
+    let xp = quest.base_experience as f64 / secs;
+    let gold = quest.base_silver as f64 / secs;
+    let mut score = objective_score(xp, gold, &settings.quests);
+    if let Some(item) = &quest.item {
+        let equipped = equipped_counterpart(gs, item);
+        let item_weights = ItemWeights::for_class(gs.character.class, &settings.equip);
+        if is_better_item(item.clone(), equipped, &item_weights) {
+            score *= 1.0 + settings.quests.item_bonus;
+        }
+    }
+    score
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use sf_api::gamestate::tavern::{CurrentAction, ExpeditionStage};
+use sf_api::gamestate::GameState;
+
+/// The things a character does, each with its own next due time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Activity {
+    /// Quests and expeditions, which share the thirst for adventure
+    Tavern,
+    Dungeons,
+    Arena,
+}
+
+impl Activity {
+    pub const ALL: [Activity; 3] = [
+        Activity::Tavern,
+        Activity::Dungeons,
+        Activity::Arena,
+    ];
+}
+
+/// Knows when each activity of one character becomes actionable again, so the
+/// main loop can sleep exactly until then instead of polling
+#[derive(Debug, Clone, Default)]
+pub struct Schedule {
+    due: HashMap<Activity, DateTime<Local>>,
+}
+
+impl Schedule {
+    /// Activities without a known due time are always due
+    pub fn is_due(&self, activity: Activity, now: DateTime<Local>) -> bool {
+        self.due.get(&activity).map_or(true, |due| *due <= now)
+    }
+
+    pub fn any_due(&self, now: DateTime<Local>) -> bool {
+        Activity::ALL.iter().any(|a| self.is_due(*a, now))
+    }
+
+    /// Recalculates the due times from the game state after a cycle
+    pub fn update(&mut self, gs: &GameState, idle_retry: Duration) {
+        let tavern = match &gs.tavern.current_action {
+            CurrentAction::Quest { busy_until, .. } => Some(*busy_until),
+            CurrentAction::CityGuard { busy_until, .. } => Some(*busy_until),
+            _ => match gs.tavern.expeditions.active().map(|e| e.current_stage()) {
+                Some(ExpeditionStage::Waiting(until)) => Some(until),
+                _ => None,
+            },
+        };
+        self.set_due(
+            [
+                (Activity::Tavern, tavern),
+                (Activity::Dungeons, gs.dungeons.next_free_fight),
+                (Activity::Arena, gs.arena.next_free_fight),
+            ],
+            Local::now(),
+            idle_retry,
+        );
+    }
+
+    /// Anything without a time or that would already be due again had nothing
+    /// to do in this cycle (e.g. no thirst left, no enemies left), so it is
+    /// retried after `idle_retry`
+    fn set_due(&mut self, times: [(Activity, Option<DateTime<Local>>); 3], now: DateTime<Local>, idle_retry: Duration) {
+        let retry = now + chrono::Duration::from_std(idle_retry).unwrap_or_default();
+        for (activity, time) in times {
+            let due = time.filter(|t| *t > now).unwrap_or(retry);
+            self.due.insert(activity, due);
+        }
+    }
+
+    /// The earliest point in time at which anything becomes actionable
+    pub fn next_wakeup(&self) -> Option<DateTime<Local>> {
+        self.due.values().min().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn unknown_activities_are_due() {
+        let schedule = Schedule::default();
+        assert!(schedule.any_due(Local::now()));
+        assert_eq!(schedule.next_wakeup(), None);
+    }
+
+    #[test]
+    fn future_times_are_kept() {
+        let now = Local::now();
+        let quest_done = now + chrono::Duration::minutes(5);
+        let mut schedule = Schedule::default();
+        schedule.set_due(
+            [
+                (Activity::Tavern, Some(quest_done)),
+                (Activity::Dungeons, Some(now + chrono::Duration::hours(1))),
+                (Activity::Arena, Some(now + chrono::Duration::minutes(10))),
+            ],
+            now,
+            IDLE,
+        );
+        assert!(!schedule.any_due(now));
+        assert!(schedule.is_due(Activity::Tavern, quest_done));
+        assert!(!schedule.is_due(Activity::Arena, quest_done));
+        assert_eq!(schedule.next_wakeup(), Some(quest_done));
+    }
+
+    #[test]
+    fn past_and_missing_times_wait_for_the_idle_retry() {
+        let now = Local::now();
+        let retry = now + chrono::Duration::seconds(60);
+        let mut schedule = Schedule::default();
+        schedule.set_due(
+            [
+                (Activity::Tavern, None),
+                (Activity::Dungeons, Some(now - chrono::Duration::minutes(1))),
+                (Activity::Arena, Some(now)),
+            ],
+            now,
+            IDLE,
+        );
+        assert!(!schedule.any_due(now));
+        assert!(Activity::ALL.iter().all(|a| schedule.is_due(*a, retry)));
+        assert_eq!(schedule.next_wakeup(), Some(retry));
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use sf_api::command::Command;
+use sf_api::SimpleSession;
+use crate::credentials::Credentials;
+use crate::error::{BotError, Recovery};
+use crate::scheduler::Schedule;
+use crate::tavern::DailyPlan;
+
+/// Delay before the first re-login attempt. Doubled for every failed attempt
+const RELOGIN_BASE_DELAY: Duration = Duration::from_secs(30);
+const RELOGIN_MAX_DELAY: Duration = Duration::from_secs(60 * 30);
+
+/// One account we play, with the characters we got from its last login
+pub struct BotAccount {
+    credentials: Credentials,
+    pub characters: Vec<BotCharacter>,
+    /// The last login failed or never happened
+    logged_out: bool,
+    failed_logins: u32,
+    next_login: Option<DateTime<Local>>,
+}
+
+impl BotAccount {
+    pub fn new(credentials: Credentials) -> Self {
+        BotAccount {
+            credentials,
+            characters: Vec::new(),
+            logged_out: true,
+            failed_logins: 0,
+            next_login: None,
+        }
+    }
+
+    /// Whether the account or any of its characters has no valid session
+    pub fn needs_login(&self) -> bool {
+        self.logged_out || self.characters.iter().any(|c| c.needs_login)
+    }
+
+    /// When the next login attempt may happen, if we are waiting for one
+    pub fn next_login(&self) -> Option<DateTime<Local>> {
+        self.next_login.filter(|_| self.needs_login())
+    }
+
+    /// Logs the whole account in, unless the backoff of previous failures is
+    /// still running. An SSO login yields new sessions for all characters at
+    /// once, so every character gets its new one, not only the rejected ones
+    pub async fn login(&mut self) {
+        if self.next_login.map_or(false, |t| t > Local::now()) {
+            return;
+        }
+        let sessions = match self.credentials.login().await {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                error!("Login of {} failed: {:?}", self.credentials.username, e);
+                self.login_failed();
+                return;
+            }
+        };
+        info!("Logged in {} successfully.", self.credentials.username);
+        self.logged_out = false;
+        let mut skipped = 0;
+        for mut session in sessions {
+            // SSO sessions have no game state before their first command, so
+            // we only know which character a session belongs to after this
+            if let Err(e) = session.send_command(Command::Update).await {
+                error!("Could not load a character of {}: {}", self.credentials.username, BotError::from(e));
+                skipped += 1;
+                continue;
+            }
+            let character = BotCharacter::new(session);
+            match self.characters.iter_mut().find(|c| c.id == character.id) {
+                Some(existing) => {
+                    existing.name = character.name;
+                    existing.session = character.session;
+                    existing.needs_login = false;
+                }
+                None => self.characters.push(character),
+            }
+        }
+        if skipped > 0 {
+            // A character we could not load may be one of ours, so keep them
+            // all and try again later
+            self.logged_out = true;
+            self.login_failed();
+            return;
+        }
+        for character in self.characters.iter().filter(|c| c.needs_login) {
+            error!("{} is no longer part of account {}", character.name, self.credentials.username);
+        }
+        self.characters.retain(|c| !c.needs_login);
+        self.failed_logins = 0;
+        self.next_login = None;
+    }
+
+    fn login_failed(&mut self) {
+        let delay = RELOGIN_BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(self.failed_logins))
+            .min(RELOGIN_MAX_DELAY);
+        self.failed_logins += 1;
+        self.next_login = Some(Local::now() + chrono::Duration::from_std(delay).unwrap_or_default());
+        warn!("Trying to log in {} again in {}s", self.credentials.username, delay.as_secs());
+    }
+}
+
+/// Identifies a character across logins. Names can change and SSO accounts
+/// may have characters of the same name on different servers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CharacterId {
+    pub server: String,
+    pub player_id: u32,
+}
+
+impl CharacterId {
+    /// The id of the character a session plays, once it has a game state
+    pub fn of(session: &SimpleSession) -> Option<Self> {
+        let gs = session.game_state()?;
+        Some(CharacterId {
+            server: session.server_url().host_str().unwrap_or_default().to_string(),
+            player_id: gs.character.player_id,
+        })
+    }
+}
+
+/// One character we play, together with the session we keep alive for it
+/// across cycles
+pub struct BotCharacter {
+    pub id: CharacterId,
+    /// Refreshed on every login and cycle, since characters can be renamed
+    pub name: String,
+    pub session: SimpleSession,
+    pub schedule: Schedule,
+    pub tavern_plan: DailyPlan,
+    /// Set once the server rejected the session
+    needs_login: bool,
+}
+
+impl BotCharacter {
+    /// Expects a session that already has a game state
+    pub fn new(session: SimpleSession) -> Self {
+        let id = CharacterId::of(&session).unwrap_or_default();
+        let name = session
+            .game_state()
+            .map(|gs| gs.character.name.clone())
+            .unwrap_or_default();
+        BotCharacter {
+            id,
+            name,
+            session,
+            schedule: Schedule::default(),
+            tavern_plan: DailyPlan::default(),
+            needs_login: false,
+        }
+    }
+
+    pub fn needs_login(&self) -> bool {
+        self.needs_login
+    }
+
+    /// Checks an error and marks the session for a re-login if the server no
+    /// longer accepts it
+    pub fn check_error(&mut self, e: &BotError) {
+        if e.recovery() == Recovery::Relogin {
+            warn!("Session of {} was rejected: {:?}", self.name, e);
+            self.needs_login = true;
+        }
+    }
+}
+
+/// Logs in every account once. Accounts that fail stay in the list and are
+/// retried with the same backoff as rejected sessions
+pub async fn login_all(accounts: Vec<Credentials>) -> Vec<BotAccount> {
+    let mut all = Vec::new();
+    for credentials in accounts {
+        let mut account = BotAccount::new(credentials);
+        account.login().await;
+        all.push(account);
+    }
+    all
+}
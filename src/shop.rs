@@ -0,0 +1,90 @@
+use sf_api::command::Command;
+use sf_api::gamestate::items::{Item, PlayerItemPlace};
+use sf_api::gamestate::GameState;
+use sf_api::gamestate::unlockables::ShopType;
+use sf_api::misc::EnumMapGet;
+use sf_api::SimpleSession;
+use crate::config::BotSettings;
+use crate::equiping_best_item::{can_wear, equipment_slot, equipped_counterpart, item_sources, item_score, ItemWeights};
+use crate::error::BotResult;
+use crate::functions::log_to_file;
+
+const SHOPS: [ShopType; 2] = [ShopType::Weapon, ShopType::Magic];
+
+pub struct Shopping<'a> {
+    session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
+}
+
+impl<'a> Shopping<'a> {
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings) -> Self {
+        Shopping { session, settings }
+    }
+
+    /// Buys shop items that beat what we own for their slot by the configured
+    /// margin, best improvement first, as long as we stay above the silver
+    /// reserve. The bought items land in the bag, where the equip pass picks
+    /// them up
+    pub async fn buy_gear(&mut self) -> BotResult<()> {
+        let shopping = &self.settings.shopping;
+        // Every purchase changes the shop and our silver, so we look again
+        // after each one
+        loop {
+            let gs = self.session.send_command(Command::Update).await?;
+            let Some(free_pos) = gs.character.inventory.bag.iter().position(Option::is_none) else {
+                log_to_file("No free bag slot to buy items into").await?;
+                break;
+            };
+            let weights = ItemWeights::for_class(gs.character.class, &self.settings.equip);
+            let budget = gs.character.silver.saturating_sub(shopping.silver_reserve);
+
+            let mut best: Option<(ShopType, usize, f64, u32)> = None;
+            for shop_type in SHOPS {
+                for (pos, item) in gs.shops.get(shop_type).items.iter().enumerate() {
+                    // Mushrooms are too valuable for regular gear
+                    if item.mushroom_price > 0 || item.price as u64 > budget {
+                        continue;
+                    }
+                    if equipment_slot(&item.typ).is_none() || !can_wear(item, gs.character.class) {
+                        continue;
+                    }
+                    let current = best_owned_score(gs, item, &weights);
+                    let score = item_score(item, &weights);
+                    if score <= current * (1.0 + shopping.min_gain) {
+                        continue;
+                    }
+                    let gain = score - current;
+                    if best.map_or(true, |(_, _, best_gain, _)| gain > best_gain) {
+                        best = Some((shop_type, pos, gain, item.price));
+                    }
+                }
+            }
+
+            let Some((shop_type, shop_pos, gain, price)) = best else {
+                break;
+            };
+            self.session.send_command(Command::BuyShop {
+                shop_type,
+                shop_pos,
+                inventory: PlayerItemPlace::MainInventory,
+                inventory_pos: free_pos,
+            }).await?;
+            log_to_file(&format!("Bought item {} from the {:?} shop for {} (score {:+.1})", shop_pos, shop_type, price, gain)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Score of the best item we already have for the slot the given item goes
+/// into, worn or waiting in the bag or fortress chest. Comparing against the
+/// bag too keeps us from buying a second item for a slot in the same pass
+fn best_owned_score(gs: &GameState, item: &Item, weights: &ItemWeights) -> f64 {
+    let slot = equipment_slot(&item.typ);
+    let equipped = equipped_counterpart(gs, item).map_or(0.0, |e| item_score(&e, weights));
+    item_sources(gs)
+        .into_iter()
+        .flat_map(|(_, items)| items.iter().flatten())
+        .filter(|i| equipment_slot(&i.typ) == slot && can_wear(i, gs.character.class))
+        .map(|i| item_score(i, weights))
+        .fold(equipped, f64::max)
+}
@@ -0,0 +1,51 @@
+use sf_api::gamestate::social::OtherPlayer;
+use sf_api::gamestate::GameState;
+use sf_api::simulate::{
+    Battle, BattleFighter, BattleSide, Monster, PlayerFighterSquad, UpgradeableFighter,
+};
+
+/// Estimated chance (0.0 - 1.0) of our character beating another player in
+/// the arena, based on `iterations` simulated fights
+pub fn win_rate_vs_player(gs: &GameState, enemy: &OtherPlayer, iterations: u32) -> f64 {
+    let squad = PlayerFighterSquad::new(gs);
+    let us = BattleFighter::from_upgradeable(&squad.character);
+    let them = BattleFighter::from_upgradeable(&UpgradeableFighter::from_other(enemy));
+    win_rate(vec![us], vec![them], iterations)
+}
+
+/// Estimated chance (0.0 - 1.0) of beating a monster. With `companions` the
+/// companions fight first, like they do in the tower
+pub fn win_rate_vs_monster(gs: &GameState, monster: &Monster, iterations: u32, companions: bool) -> f64 {
+    let squad = PlayerFighterSquad::new(gs);
+    let mut ours = Vec::new();
+    if companions {
+        if let Some(comps) = &squad.companions {
+            ours.extend(comps.values().map(BattleFighter::from_upgradeable));
+        }
+    }
+    ours.push(BattleFighter::from_upgradeable(&squad.character));
+    win_rate(ours, vec![BattleFighter::from_monster(monster)], iterations)
+}
+
+/// Estimated chance of beating the current enemy of the player portal. The
+/// enemy keeps the damage of earlier days, so this is a lower bound
+pub fn win_rate_vs_portal(gs: &GameState, iterations: u32) -> Option<f64> {
+    let monster = gs.dungeons.current_portal_enemy()?;
+    Some(win_rate_vs_monster(gs, monster, iterations, false))
+}
+
+fn win_rate(ours: Vec<BattleFighter>, theirs: Vec<BattleFighter>, iterations: u32) -> f64 {
+    if iterations == 0 {
+        return 0.0;
+    }
+    let mut wins = 0;
+    for _ in 0..iterations {
+        let mut left = ours.clone();
+        let mut right = theirs.clone();
+        let mut battle = Battle::new(&mut left, &mut right);
+        if battle.simulate(&mut ()) == BattleSide::Left {
+            wins += 1;
+        }
+    }
+    wins as f64 / iterations as f64
+}
@@ -0,0 +1,155 @@
+use chrono::{Local, NaiveDate};
+use sf_api::command::{Command, ExpeditionSetting};
+use sf_api::gamestate::rewards::Event::{EpicQuestExtravaganza, ExceptionalXPEvent};
+use sf_api::gamestate::tavern::CurrentAction;
+use sf_api::gamestate::GameState;
+use sf_api::SimpleSession;
+use crate::config::{BotSettings, TavernStrategy};
+use crate::error::BotResult;
+use crate::functions::log_to_file;
+use crate::quest::{objective_score, Questing};
+use crate::Expedision::Exping;
+
+/// What we spend our thirst for adventure on today
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TavernPlan {
+    Quests,
+    Expeditions,
+}
+
+/// Remembers the plan of a character, so the decision is only made once per
+/// day (the questing preference can only be changed once per day anyways)
+#[derive(Debug, Clone, Default)]
+pub struct DailyPlan {
+    decided: Option<(NaiveDate, TavernPlan)>,
+}
+
+pub struct Tavern<'a> {
+    session: &'a mut SimpleSession,
+    settings: &'a BotSettings,
+    plan: &'a mut DailyPlan,
+}
+
+impl<'a> Tavern<'a> {
+    pub fn new(session: &'a mut SimpleSession, settings: &'a BotSettings, plan: &'a mut DailyPlan) -> Self {
+        Tavern { session, settings, plan }
+    }
+
+    pub async fn run(&mut self) -> BotResult<()> {
+        let gs = self.session.send_command(Command::Update).await?;
+        // Whatever is already running has to be finished first
+        let running = match gs.tavern.current_action {
+            CurrentAction::Quest { .. } => Some(TavernPlan::Quests),
+            // Questing knows how to collect the city guard reward
+            CurrentAction::CityGuard { .. } => Some(TavernPlan::Quests),
+            _ if gs.tavern.expeditions.active().is_some() => Some(TavernPlan::Expeditions),
+            _ => None,
+        };
+
+        let plan = match running {
+            Some(plan) => plan,
+            None => {
+                let plan = self.todays_plan().await?;
+                self.apply_preference(plan).await?
+            }
+        };
+
+        match plan {
+            TavernPlan::Quests => Questing::new(self.session, self.settings).questing().await,
+            TavernPlan::Expeditions => Exping::new(self.session, self.settings).Exping().await,
+        }
+    }
+
+    async fn todays_plan(&mut self) -> BotResult<TavernPlan> {
+        let today = Local::now().date_naive();
+        if let Some((day, plan)) = self.plan.decided {
+            if day == today {
+                return Ok(plan);
+            }
+        }
+        let gs = self.session.send_command(Command::Update).await?;
+        let plan = match self.settings.tavern.strategy {
+            TavernStrategy::Quests => TavernPlan::Quests,
+            TavernStrategy::Expeditions => TavernPlan::Expeditions,
+            TavernStrategy::Auto => auto_plan(gs, self.settings),
+        };
+        log_to_file(&format!("Today we are doing {:?}", plan)).await?;
+        self.plan.decided = Some((today, plan));
+        Ok(plan)
+    }
+
+    /// Makes sure the tavern offers what we planned. If the preference was
+    /// already changed today, we go with what the tavern offers instead
+    async fn apply_preference(&mut self, plan: TavernPlan) -> BotResult<TavernPlan> {
+        let gs = self.session.send_command(Command::Update).await?;
+        let wanted = match plan {
+            TavernPlan::Quests => ExpeditionSetting::PreferQuests,
+            TavernPlan::Expeditions => ExpeditionSetting::PreferExpeditions,
+        };
+        if gs.tavern.questing_preference == wanted {
+            return Ok(plan);
+        }
+        if !gs.tavern.can_change_questing_preference() {
+            let actual = match gs.tavern.questing_preference {
+                ExpeditionSetting::PreferQuests => TavernPlan::Quests,
+                ExpeditionSetting::PreferExpeditions => TavernPlan::Expeditions,
+            };
+            log_to_file(&format!("The questing setting can not be changed today, doing {:?}", actual)).await?;
+            self.plan.decided = Some((Local::now().date_naive(), actual));
+            return Ok(actual);
+        }
+        log_to_file(&format!("Changing questing setting to {:?}", wanted)).await?;
+        self.session
+            .send_command(Command::SetQuestsInsteadOfExpeditions { value: wanted })
+            .await?;
+        Ok(plan)
+    }
+}
+
+/// Quests win if expeditions are not available, if an event boosts quests,
+/// or if the best quest on offer beats the estimate for expeditions. Both are
+/// scored per thirst second by the `[quests]` objective
+fn auto_plan(gs: &GameState, settings: &BotSettings) -> TavernPlan {
+    if !gs.tavern.expeditions.is_event_ongoing() {
+        return TavernPlan::Quests;
+    }
+    let quest_event = gs
+        .specials
+        .events
+        .active
+        .iter()
+        .any(|e| *e == ExceptionalXPEvent || *e == EpicQuestExtravaganza);
+    if quest_event {
+        return TavernPlan::Quests;
+    }
+    // Xp and silver per thirst second of every quest on offer
+    let rates: Vec<(f64, f64)> = gs
+        .tavern
+        .quests
+        .iter()
+        .filter(|q| q.base_length > 0)
+        .map(|q| {
+            let secs = q.base_length as f64;
+            (q.base_experience as f64 / secs, q.base_silver as f64 / secs)
+        })
+        .collect();
+    if rates.is_empty() {
+        return TavernPlan::Expeditions;
+    }
+    let tavern = &settings.tavern;
+    let average = |rate: fn(&(f64, f64)) -> f64| rates.iter().map(rate).sum::<f64>() / rates.len() as f64;
+    let expedition_xp = tavern.expedition_xp_per_thirst_sec
+        .unwrap_or_else(|| tavern.expedition_quest_ratio * average(|r| r.0));
+    let expedition_silver = tavern.expedition_silver_per_thirst_sec
+        .unwrap_or_else(|| tavern.expedition_quest_ratio * average(|r| r.1));
+    let expedition_score = objective_score(expedition_xp, expedition_silver, &settings.quests);
+    let best_quest_score = rates
+        .iter()
+        .map(|(xp, silver)| objective_score(*xp, *silver, &settings.quests))
+        .fold(0.0, f64::max);
+    if best_quest_score > expedition_score {
+        TavernPlan::Quests
+    } else {
+        TavernPlan::Expeditions
+    }
+}
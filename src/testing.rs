@@ -0,0 +1,32 @@
+use crate::config::{Config, TavernStrategy};
+
+#[test]
+fn example_config_parses() {
+    let config = Config::parse(include_str!("../config.example.toml")).unwrap();
+    assert_eq!(config.sleep_secs, 60);
+    assert_eq!(config.defaults.tavern.strategy, TavernStrategy::Expeditions);
+}
+
+#[test]
+fn character_settings_are_merged_over_defaults() {
+    let config = Config::parse(r#"
+        [defaults.activities]
+        arena = false
+
+        [defaults.equip]
+        luck = 3
+
+        [characters.MyChar.equip]
+        constitution = 7
+    "#).unwrap();
+    let settings = config.for_character("MyChar");
+    assert!(!settings.activities.arena);
+    assert_eq!(settings.equip.luck, 3);
+    assert_eq!(settings.equip.constitution, 7);
+    assert_eq!(config.for_character("Other").equip.constitution, 4);
+}
+
+#[test]
+fn unknown_config_keys_are_rejected() {
+    assert!(Config::parse("[defaults.equip]\nlcuk = 4\n").is_err());
+}